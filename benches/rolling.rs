@@ -0,0 +1,155 @@
+// Rolling profile throughput for each `Parallel` mode, over a small and a
+// large window, against the fixed `threads * 64` chunks the kernel used
+// before `Parallel` and against the original kernel, which filtered polars
+// series once per bin with a rayon split nested in every window. Run with
+// `cargo bench --no-default-features`.
+use criterion::{black_box, criterion_group, criterion_main, BenchmarkId, Criterion};
+use polars::prelude::*;
+use polars_pbv::profile::{rolling_profile_with, Parallel, ProfileConfig, VolumeProfile};
+use rayon::prelude::*;
+
+// a deterministic random walk with uneven volume
+fn ticks(len: usize) -> (Vec<f64>, Vec<f64>) {
+    let mut state = 0x2545_f491_4f6c_dd1d_u64;
+    let mut next = || {
+        state ^= state << 13;
+        state ^= state >> 7;
+        state ^= state << 17;
+        state as f64 / u64::MAX as f64
+    };
+    let mut price = Vec::with_capacity(len);
+    let mut volume = Vec::with_capacity(len);
+    let mut last = 100.0;
+    for _ in 0..len {
+        last += (next() - 0.5) * 0.2;
+        price.push(last);
+        volume.push(1.0 + 99.0 * next() * next());
+    }
+    (price, volume)
+}
+
+// The old split: `threads * 64` chunks whatever the input and window size,
+// each replaying the window before its first row.
+fn sixty_four_chunks(
+    price: &[f64],
+    volume: &[f64],
+    config: &ProfileConfig,
+) -> Vec<Option<VolumeProfile>> {
+    let len = price.len();
+    let chunk_rows = len.div_ceil(rayon::current_num_threads() * 64).max(1);
+    (0..len.div_ceil(chunk_rows))
+        .into_par_iter()
+        .flat_map_iter(|chunk| {
+            let first = chunk * chunk_rows;
+            let end = (first + chunk_rows).min(len);
+            let from = first.saturating_sub(config.window_size - 1);
+            let rows =
+                rolling_profile_with(&price[from..end], &volume[from..end], config, Parallel::Off)
+                    .unwrap();
+            // the warm-up rows belong to the chunk before
+            rows.into_iter().skip(first - from)
+        })
+        .collect()
+}
+
+// The original `price_by_volume_par`, volume only, without `pct` and
+// rounding: `threads * 64` chunks of rows and, inside every window, a
+// parallel masked sum per bin.
+fn baseline(
+    price: &Series,
+    volume: &Series,
+    window_size: usize,
+    bins: usize,
+) -> Vec<Option<Series>> {
+    let price_len = price.len();
+    let thread_count = rayon::current_num_threads() * 64;
+    let chunk_size = price_len.div_ceil(thread_count);
+    (0..thread_count)
+        .into_par_iter()
+        .flat_map(|thread_idx| {
+            let start_idx = thread_idx * chunk_size + 1;
+            let end_idx = ((thread_idx + 1) * chunk_size + 1).min(price_len + 1);
+            (start_idx..end_idx)
+                .map(|i| {
+                    if i < window_size {
+                        return None;
+                    }
+                    let start = (i - window_size) as i64;
+                    let window_price = price.slice(start, window_size);
+                    let window_volume = volume.slice(start, window_size);
+                    let max_price: f64 = window_price.max().unwrap().unwrap();
+                    let min_price: f64 = window_price.min().unwrap().unwrap();
+                    let interval = (max_price - min_price) / bins as f64;
+                    let volume_at_price: Vec<f64> = (0..bins)
+                        .into_par_iter()
+                        .map(|n| {
+                            let lower_bound = min_price + n as f64 * interval;
+                            let upper_bound = min_price + (n + 1) as f64 * interval;
+                            let mask = if n == bins - 1 {
+                                window_price.gt_eq(lower_bound).unwrap()
+                            } else {
+                                window_price.gt_eq(lower_bound).unwrap()
+                                    & window_price.lt(upper_bound).unwrap()
+                            };
+                            window_volume.filter(&mask).unwrap().sum().unwrap()
+                        })
+                        .collect();
+                    Some(Series::new("volume", &volume_at_price))
+                })
+                .collect::<Vec<_>>()
+        })
+        .collect()
+}
+
+// The original kernel is far slower, so it runs on fewer ticks.
+fn against_baseline(c: &mut Criterion) {
+    let (price, volume) = ticks(10_000);
+    let (price_s, volume_s) = (Series::new("price", &price), Series::new("volume", &volume));
+    let mut group = c.benchmark_group("against_baseline");
+    group.sample_size(10);
+    for window_size in [20, 2_000] {
+        let config = ProfileConfig::new(window_size, 20);
+        group.bench_with_input(
+            BenchmarkId::new("baseline", window_size),
+            &config,
+            |b, _| b.iter(|| baseline(black_box(&price_s), black_box(&volume_s), window_size, 20)),
+        );
+        for (name, parallel) in [("off", Parallel::Off), ("auto", Parallel::Auto)] {
+            group.bench_with_input(BenchmarkId::new(name, window_size), &config, |b, config| {
+                b.iter(|| {
+                    rolling_profile_with(black_box(&price), black_box(&volume), config, parallel)
+                })
+            });
+        }
+    }
+    group.finish();
+}
+
+fn rolling(c: &mut Criterion) {
+    let (price, volume) = ticks(100_000);
+    let mut group = c.benchmark_group("rolling_profile");
+    group.sample_size(10);
+    for window_size in [20, 2_000] {
+        let config = ProfileConfig::new(window_size, 20);
+        for (name, parallel) in [
+            ("off", Parallel::Off),
+            ("auto", Parallel::Auto),
+            ("force", Parallel::Force),
+        ] {
+            group.bench_with_input(BenchmarkId::new(name, window_size), &config, |b, config| {
+                b.iter(|| {
+                    rolling_profile_with(black_box(&price), black_box(&volume), config, parallel)
+                })
+            });
+        }
+        group.bench_with_input(
+            BenchmarkId::new("x64", window_size),
+            &config,
+            |b, config| b.iter(|| sixty_four_chunks(black_box(&price), black_box(&volume), config)),
+        );
+    }
+    group.finish();
+}
+
+criterion_group!(benches, rolling, against_baseline);
+criterion_main!(benches);
@@ -1,10 +1,11 @@
 from __future__ import annotations
 
 from pathlib import Path
-from typing import TYPE_CHECKING
+from typing import TYPE_CHECKING, Sequence
 
 import polars as pl
 
+from polars_pbv.config import PbvConfig, Smoothing, profile_kwargs, smoothing_kwargs
 from polars_pbv.utils import parse_into_expr, register_plugin, parse_version
 
 if TYPE_CHECKING:
@@ -42,60 +43,474 @@ def pbv_not_par(
     )
 
 def pbv(
+    price: IntoExpr,
+    volume: IntoExpr | Sequence[IntoExpr],
+    window_size: int | None = None,
+    bins: int | None = None,
+    center: bool | None = None,
+    round: int | None = None,
+    by: IntoExpr | None = None,
+    weight: str = "volume",
+    period: IntoExpr | None = None,
+    sparse: bool = False,
+    config: PbvConfig | None = None,
+    normalize: str | None = None,
+    smoothing: Smoothing | None = None,
+    label: str | None = None,
+    closed: str | None = None,
+    parallel: str = "auto",
+) -> pl.Expr:
+    price = parse_into_expr(price)
+    # a list of weight columns gives one field per column, named after it
+    multi = isinstance(volume, (list, tuple))
+    volumes = list(volume) if multi else [volume]
+    args: list[IntoExpr] = [price, *(parse_into_expr(v) for v in volumes)]
+    if by is not None:
+        args.append(parse_into_expr(by))
+    if period is not None:
+        args.append(parse_into_expr(period))
+    return register_plugin(
+        args=args,
+        symbol="pbv",
+        is_elementwise=False,
+        lib=lib,
+        kwargs={
+            **profile_kwargs(window_size, bins, center, round, config),
+            "weight": weight,
+            "has_by": by is not None,
+            "has_period": period is not None,
+            "n_weights": len(volumes) if multi else None,
+            "sparse": sparse,
+            "normalize": normalize,
+            "smoothing": smoothing_kwargs(smoothing),
+            "label": label,
+            "closed": closed,
+            "parallel": parallel,
+        },
+    )
+
+def pbv_pct(
+    price: IntoExpr,
+    volume: IntoExpr | Sequence[IntoExpr],
+    window_size: int | None = None,
+    bins: int | None = None,
+    center: bool | None = None,
+    round: int | None = None,
+    by: IntoExpr | None = None,
+    weight: str = "volume",
+    period: IntoExpr | None = None,
+    sparse: bool = False,
+    config: PbvConfig | None = None,
+    normalize: str | None = None,
+    smoothing: Smoothing | None = None,
+    label: str | None = None,
+    closed: str | None = None,
+    parallel: str = "auto",
+) -> pl.Expr:
+    price = parse_into_expr(price)
+    # a list of weight columns gives one field per column, named after it
+    multi = isinstance(volume, (list, tuple))
+    volumes = list(volume) if multi else [volume]
+    args: list[IntoExpr] = [price, *(parse_into_expr(v) for v in volumes)]
+    if by is not None:
+        args.append(parse_into_expr(by))
+    if period is not None:
+        args.append(parse_into_expr(period))
+    return register_plugin(
+        args=args,
+        symbol="pbv_pct",
+        is_elementwise=False,
+        lib=lib,
+        kwargs={
+            **profile_kwargs(window_size, bins, center, round, config),
+            "weight": weight,
+            "has_by": by is not None,
+            "has_period": period is not None,
+            "n_weights": len(volumes) if multi else None,
+            "sparse": sparse,
+            "normalize": normalize,
+            "smoothing": smoothing_kwargs(smoothing),
+            "label": label,
+            "closed": closed,
+            "parallel": parallel,
+        },
+    )
+
+
+def pbv_agg(
     price: IntoExpr,
     volume: IntoExpr,
-    window_size: int,
-    bins: int,
-    center: bool = True,
-    round: int = -1,
+    bins: int | None = None,
+    center: bool | None = None,
+    round: int | None = None,
+    pct: bool = False,
+    weight: str = "volume",
+    period: IntoExpr | None = None,
+    config: PbvConfig | None = None,
+    normalize: str | None = None,
+    smoothing: Smoothing | None = None,
+    label: str | None = None,
+    closed: str | None = None,
+) -> pl.Expr:
+    price = parse_into_expr(price)
+    volume = parse_into_expr(volume)
+    args: list[IntoExpr] = [price, volume]
+    if period is not None:
+        args.append(parse_into_expr(period))
+    return register_plugin(
+        args=args,
+        symbol="pbv_agg",
+        is_elementwise=False,
+        returns_scalar=True,
+        lib=lib,
+        kwargs={
+            **profile_kwargs(0, bins, center, round, config),
+            "pct": pct,
+            "weight": weight,
+            "has_period": period is not None,
+            "normalize": normalize,
+            "smoothing": smoothing_kwargs(smoothing),
+            "label": label,
+            "closed": closed,
+        },
+    )
+
+
+def pbv_list(
+    price: IntoExpr,
+    volume: IntoExpr,
+    bins: int | None = None,
+    center: bool | None = None,
+    round: int | None = None,
+    pct: bool = False,
+    config: PbvConfig | None = None,
+    normalize: str | None = None,
+    smoothing: Smoothing | None = None,
+    label: str | None = None,
+    closed: str | None = None,
 ) -> pl.Expr:
     price = parse_into_expr(price)
     volume = parse_into_expr(volume)
     return register_plugin(
         args=[price, volume],
-        symbol="pbv",
+        symbol="pbv_list",
+        is_elementwise=True,
+        lib=lib,
+        kwargs={
+            **profile_kwargs(0, bins, center, round, config),
+            "pct": pct,
+            "normalize": normalize,
+            "smoothing": smoothing_kwargs(smoothing),
+            "label": label,
+            "closed": closed,
+        },
+    )
+
+
+def pbv_merge(
+    pbv: IntoExpr,
+    bins: int | None = None,
+    tick_size: float | None = None,
+    center: bool | None = None,
+    round: int | None = None,
+    pct: bool = False,
+    normalize: str | None = None,
+    config: PbvConfig | None = None,
+    input_label: str = "center",
+    input_width: float | None = None,
+) -> pl.Expr:
+    # a config brings its own grid, which `bins` or `tick_size` may replace
+    if bins is not None and tick_size is not None:
+        raise ValueError("at most one of `bins` and `tick_size` can be set")
+    if config is None and bins is None and tick_size is None:
+        raise ValueError("exactly one of `bins` and `tick_size` must be set")
+    pbv = parse_into_expr(pbv)
+    return register_plugin(
+        args=[pbv],
+        symbol="pbv_merge",
         is_elementwise=False,
+        returns_scalar=True,
         lib=lib,
         kwargs={
-            "window_size": window_size,
             "bins": bins,
+            "tick_size": tick_size,
             "center_label": center,
             "round": round,
+            "pct": pct,
+            "normalize": normalize,
+            "config": None if config is None else config.to_kwargs(),
+            "input_label": input_label,
+            "input_width": input_width,
         },
     )
 
-def pbv_pct(
+
+def pbv_distance(
     price: IntoExpr,
     volume: IntoExpr,
-    window_size: int,
-    bins: int,
-    center: bool = True,
-    round: int = -1,
+    window_size: int | None = None,
+    bins: int | None = None,
+    k: int = 1,
+    config: PbvConfig | None = None,
+    smoothing: Smoothing | None = None,
+    closed: str | None = None,
+    parallel: str = "auto",
 ) -> pl.Expr:
     price = parse_into_expr(price)
     volume = parse_into_expr(volume)
     return register_plugin(
         args=[price, volume],
-        symbol="pbv_pct",
+        symbol="pbv_distance",
         is_elementwise=False,
         lib=lib,
         kwargs={
-            "window_size": window_size,
-            "bins": bins,
-            "center_label": center,
-            "round": round,
+            **profile_kwargs(window_size, bins, None, None, config),
+            "k": k,
+            "smoothing": smoothing_kwargs(smoothing),
+            "closed": closed,
+            "parallel": parallel,
         },
     )
 
 
+def pbv_stats(
+    price: IntoExpr,
+    volume: IntoExpr,
+    window_size: int | None = None,
+    bins: int | None = None,
+    config: PbvConfig | None = None,
+    smoothing: Smoothing | None = None,
+    closed: str | None = None,
+    parallel: str = "auto",
+) -> pl.Expr:
+    price = parse_into_expr(price)
+    volume = parse_into_expr(volume)
+    return register_plugin(
+        args=[price, volume],
+        symbol="pbv_stats",
+        is_elementwise=False,
+        lib=lib,
+        kwargs={
+            **profile_kwargs(window_size, bins, None, None, config),
+            "smoothing": smoothing_kwargs(smoothing),
+            "closed": closed,
+            "parallel": parallel,
+        },
+    )
+
+
+SHAPES = ["P", "b", "D", "B"]
+
+
+def pbv_shape(
+    price: IntoExpr,
+    volume: IntoExpr,
+    window_size: int | None = None,
+    bins: int | None = None,
+    upper: float = 0.6,
+    lower: float = 0.4,
+    valley: float = 0.5,
+    smoothing: Smoothing | None = None,
+    config: PbvConfig | None = None,
+    closed: str | None = None,
+    parallel: str = "auto",
+    mass: float = 0.5,
+) -> pl.Expr:
+    price = parse_into_expr(price)
+    volume = parse_into_expr(volume)
+    shape = register_plugin(
+        args=[price, volume],
+        symbol="pbv_shape",
+        is_elementwise=False,
+        lib=lib,
+        kwargs={
+            **profile_kwargs(window_size, bins, None, None, config),
+            "upper": upper,
+            "lower": lower,
+            "valley": valley,
+            "mass": mass,
+            "smoothing": smoothing_kwargs(smoothing),
+            "closed": closed,
+            "parallel": parallel,
+        },
+    )
+    # the plugin writes the class as a string: an enum made in Rust would lose its
+    # categories crossing the plugin boundary and arrive as a plain categorical
+    return shape.struct.with_fields(pl.field("shape").cast(pl.Enum(SHAPES)))
+
+
+def pbv_position(
+    price: IntoExpr,
+    volume: IntoExpr,
+    window_size: int | None = None,
+    bins: int | None = None,
+    tick_size: float | None = None,
+    value_area: float = 0.7,
+    smoothing: Smoothing | None = None,
+    config: PbvConfig | None = None,
+    closed: str | None = None,
+    parallel: str = "auto",
+) -> pl.Expr:
+    price = parse_into_expr(price)
+    volume = parse_into_expr(volume)
+    return register_plugin(
+        args=[price, volume],
+        symbol="pbv_position",
+        is_elementwise=False,
+        lib=lib,
+        kwargs={
+            **profile_kwargs(window_size, bins, None, None, config),
+            "tick_size": tick_size,
+            "value_area": value_area,
+            "smoothing": smoothing_kwargs(smoothing),
+            "closed": closed,
+            "parallel": parallel,
+        },
+    )
+
+
+def pbv_naked_poc(
+    price: IntoExpr,
+    volume: IntoExpr,
+    session: IntoExpr,
+    bins: int | None = None,
+    config: PbvConfig | None = None,
+    closed: str | None = None,
+    parallel: str = "auto",
+) -> pl.Expr:
+    price = parse_into_expr(price)
+    volume = parse_into_expr(volume)
+    session = parse_into_expr(session)
+    return register_plugin(
+        args=[price, volume, session],
+        symbol="pbv_naked_poc",
+        is_elementwise=False,
+        lib=lib,
+        kwargs={
+            # the whole session is one window
+            **profile_kwargs(0, bins, None, None, config),
+            "closed": closed,
+            "parallel": parallel,
+        },
+    )
+
+
+def pbv_developing(
+    price: IntoExpr,
+    volume: IntoExpr,
+    tick_size: float | None = None,
+    value_area: float = 0.7,
+    by: IntoExpr | None = None,
+    config: PbvConfig | None = None,
+) -> pl.Expr:
+    price = parse_into_expr(price)
+    volume = parse_into_expr(volume)
+    args: list[IntoExpr] = [price, volume]
+    if by is not None:
+        args.append(parse_into_expr(by))
+    return register_plugin(
+        args=args,
+        symbol="pbv_developing",
+        is_elementwise=False,
+        lib=lib,
+        kwargs={
+            "tick_size": tick_size,
+            "value_area": value_area,
+            "config": None if config is None else config.to_kwargs(),
+        },
+    )
+
+
+def pbv_excess(
+    price: IntoExpr,
+    volume: IntoExpr,
+    window_size: int | None = None,
+    bins: int | None = None,
+    poor_ratio: float = 1.0,
+    config: PbvConfig | None = None,
+    closed: str | None = None,
+    parallel: str = "auto",
+) -> pl.Expr:
+    price = parse_into_expr(price)
+    volume = parse_into_expr(volume)
+    return register_plugin(
+        args=[price, volume],
+        symbol="pbv_excess",
+        is_elementwise=False,
+        lib=lib,
+        kwargs={
+            **profile_kwargs(window_size, bins, None, None, config),
+            "poor_ratio": poor_ratio,
+            "closed": closed,
+            "parallel": parallel,
+        },
+    )
+
+
+def pbv_densify(pbv: IntoExpr, bins: int | None = None, round: int = -1) -> pl.Expr:
+    pbv = parse_into_expr(pbv)
+    return register_plugin(
+        args=[pbv],
+        symbol="pbv_densify",
+        is_elementwise=True,
+        lib=lib,
+        kwargs={"bins": bins, "round": round},
+    )
+
+def pbv_weighted(
+    price: IntoExpr,
+    weight: IntoExpr,
+    window_size: int | None = None,
+    bins: int | None = None,
+    center: bool | None = None,
+    round: int | None = None,
+    pct: bool = False,
+    signed: bool = False,
+    by: IntoExpr | None = None,
+    config: PbvConfig | None = None,
+    normalize: str | None = None,
+    smoothing: Smoothing | None = None,
+    label: str | None = None,
+    closed: str | None = None,
+    parallel: str = "auto",
+) -> pl.Expr:
+    price = parse_into_expr(price)
+    weight = parse_into_expr(weight)
+    args: list[IntoExpr] = [price, weight]
+    if by is not None:
+        args.append(parse_into_expr(by))
+    return register_plugin(
+        args=args,
+        symbol="pbv_weighted",
+        is_elementwise=False,
+        lib=lib,
+        kwargs={
+            **profile_kwargs(window_size, bins, center, round, config),
+            "pct": pct,
+            "signed": signed,
+            "has_by": by is not None,
+            "normalize": normalize,
+            "smoothing": smoothing_kwargs(smoothing),
+            "label": label,
+            "closed": closed,
+            "parallel": parallel,
+        },
+    )
+
 def pbv_topn_vp(
     price: IntoExpr,
     volume: IntoExpr,
-    window_size: int,
-    bins: int,
-    n: int,
-    center: bool = True,
-    round: int = -1,
+    window_size: int | None = None,
+    bins: int | None = None,
+    n: int = 1,
+    center: bool | None = None,
+    round: int | None = None,
+    config: PbvConfig | None = None,
+    normalize: str | None = None,
+    smoothing: Smoothing | None = None,
+    label: str | None = None,
+    closed: str | None = None,
+    parallel: str = "auto",
 ) -> pl.Expr:
     price = parse_into_expr(price)
     volume = parse_into_expr(volume)
@@ -105,12 +520,14 @@ def pbv_topn_vp(
         is_elementwise=False,
         lib=lib,
         kwargs={
-            "window_size": window_size,
-            "bins": bins,
+            **profile_kwargs(window_size, bins, center, round, config),
             "n": n,
-            "center_label": center,
-            "round": round,
             "pct": False,
+            "normalize": normalize,
+            "smoothing": smoothing_kwargs(smoothing),
+            "label": label,
+            "closed": closed,
+            "parallel": parallel,
         },
     )
 
@@ -118,12 +535,18 @@ def pbv_topn_vp(
 def pbv_topn_v(
     price: IntoExpr,
     volume: IntoExpr,
-    window_size: int,
-    bins: int,
-    n: int,
-    center: bool = True,
-    round: int = -1,
+    window_size: int | None = None,
+    bins: int | None = None,
+    n: int = 1,
+    center: bool | None = None,
+    round: int | None = None,
     pct: bool = False,
+    config: PbvConfig | None = None,
+    normalize: str | None = None,
+    smoothing: Smoothing | None = None,
+    label: str | None = None,
+    closed: str | None = None,
+    parallel: str = "auto",
 ) -> pl.Expr:
     price = parse_into_expr(price)
     volume = parse_into_expr(volume)
@@ -133,11 +556,46 @@ def pbv_topn_v(
         is_elementwise=False,
         lib=lib,
         kwargs={
-            "window_size": window_size,
-            "bins": bins,
+            **profile_kwargs(window_size, bins, center, round, config),
             "n": n,
-            "center_label": center,
-            "round": round,
             "pct": pct,
+            "normalize": normalize,
+            "smoothing": smoothing_kwargs(smoothing),
+            "label": label,
+            "closed": closed,
+            "parallel": parallel,
+        },
+    )
+
+
+def pbv_quantile(
+    price: IntoExpr,
+    volume: IntoExpr,
+    window_size: int | None = None,
+    quantiles: Sequence[float] = (0.5,),
+    interpolation: str = "linear",
+    by: IntoExpr | None = None,
+    config: PbvConfig | None = None,
+    parallel: str = "auto",
+) -> pl.Expr:
+    if window_size is None and config is None:
+        raise ValueError("pass `window_size` or a `config`")
+    price = parse_into_expr(price)
+    volume = parse_into_expr(volume)
+    args: list[IntoExpr] = [price, volume]
+    if by is not None:
+        args.append(parse_into_expr(by))
+    return register_plugin(
+        args=args,
+        symbol="pbv_quantile",
+        is_elementwise=False,
+        lib=lib,
+        kwargs={
+            "window_size": window_size,
+            "config": None if config is None else config.to_kwargs(),
+            "quantiles": [float(q) for q in quantiles],
+            "interpolation": interpolation,
+            "has_by": by is not None,
+            "parallel": parallel,
         },
     )
@@ -0,0 +1,91 @@
+from __future__ import annotations
+
+from dataclasses import dataclass, replace
+from typing import Any, Tuple, Union
+
+# Keep in sync with `CONFIG_VERSION` in src/profile.rs.
+CONFIG_VERSION = 1
+
+# "none", "silverman", ("gaussian", bandwidth in bins) or
+# ("moving_average", bins on each side)
+Smoothing = Union[str, Tuple[str, float]]
+
+
+@dataclass(frozen=True)
+class PbvConfig:
+    """
+    One configuration for every profile expression.
+
+    Pass it as `config=` instead of `window_size`, `bins`, `center` and
+    `round`; those that are passed as well override the config. `pct` (and
+    `signed`) scale the bins when the config leaves `normalize` at "none". Exactly one of `bins` (equal bins between the lowest and the
+    highest price of the window) and `tick_size` (bins of a fixed price step)
+    has to be set. `label` is "lower", "center", "upper", "vwap" or "index",
+    `round` the number of decimals or None, `normalize` "none", "sum",
+    "abs_sum", "max", "zscore", "density" or "cumulative". `smoothing` is
+    "none", "silverman", ("gaussian", bandwidth in bins) or
+    ("moving_average", bins on each side). `closed` is the inclusive side of
+    the inner bin edges, "left" or "right".
+    """
+
+    window_size: int = 0
+    bins: int | None = None
+    tick_size: float | None = None
+    label: str = "center"
+    round: int | None = None
+    normalize: str = "none"
+    smoothing: Smoothing = "none"
+    closed: str = "left"
+
+    def __post_init__(self) -> None:
+        if (self.bins is None) == (self.tick_size is None):
+            raise ValueError("set exactly one of `bins` and `tick_size`")
+
+    def with_options(self, **changes: Any) -> PbvConfig:
+        """A copy with the given fields changed, e.g. `config.with_options(round=2)`."""
+        return replace(self, **changes)
+
+    def to_kwargs(self) -> dict[str, Any]:
+        binning = (
+            {"bins": self.bins} if self.bins is not None else {"tick_size": self.tick_size}
+        )
+        return {
+            "version": CONFIG_VERSION,
+            "window_size": self.window_size,
+            "binning": binning,
+            "label": self.label,
+            "round": self.round,
+            "normalize": self.normalize,
+            "smoothing": smoothing_kwargs(self.smoothing),
+            "closed": self.closed,
+        }
+
+
+def profile_kwargs(
+    window_size: int | None,
+    bins: int | None,
+    center: bool | None,
+    round: int | None,
+    config: PbvConfig | None,
+) -> dict[str, Any]:
+    # without a config the old fields spell the profile, with one the fields
+    # that were passed override it
+    if config is None and (window_size is None or bins is None):
+        raise ValueError("pass `window_size` and `bins`, or a `config`")
+    return {
+        "window_size": window_size,
+        "bins": bins,
+        "center_label": center,
+        "round": round,
+        "config": None if config is None else config.to_kwargs(),
+    }
+
+
+def smoothing_kwargs(smoothing: Smoothing | None) -> Any:
+    # the plugin reads the tuples as {"gaussian": 1.5}, plain names as they are
+    if smoothing is None or isinstance(smoothing, str):
+        return smoothing
+    kind, width = smoothing
+    if kind == "moving_average":
+        return {kind: int(width)}
+    return {kind: float(width)}
@@ -54,6 +54,7 @@ def register_plugin(
     *,
     symbol: str,
     is_elementwise: bool,
+    returns_scalar: bool = False,
     kwargs: dict[str, Any] | None = None,
     args: list[IntoExpr],
     lib: str | Path,
@@ -67,6 +68,7 @@ def register_plugin(
             args=args[1:],
             kwargs=kwargs,
             is_elementwise=is_elementwise,
+            returns_scalar=returns_scalar,
         )
     from polars.plugins import register_plugin_function
 
@@ -76,6 +78,7 @@ def register_plugin(
         function_name=symbol,
         kwargs=kwargs,
         is_elementwise=is_elementwise,
+        returns_scalar=returns_scalar,
     )
 
 
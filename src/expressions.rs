@@ -2,27 +2,221 @@
 use polars::prelude::*;
 // use polars::prelude::
 use pyo3_polars::derive::polars_expr;
-use rayon::prelude::*;
-use serde::Deserialize;
+use polars::export::arrow::array::{ArrayRef, ListArray};
+use polars::export::arrow::datatypes::{ArrowDataType, Field as ArrowField};
+use polars::export::arrow::offset::OffsetsBuffer;
+use pyo3_polars::export::polars_core::POOL;
+use serde::{Deserialize, Serialize};
+use std::collections::VecDeque;
 
-#[derive(Deserialize)]
+use crate::profile::{
+    map_rows, normalize, poc_index, priced, rolling_bins, rolling_map, round_to, smooth,
+    value_area_around, value_area_index, volume_quantiles, Binning, Closed, ConfigError, Grid,
+    Interpolation, Label, Normalize, Parallel, ProfileConfig, RollingBins, Smoothing,
+    VolumeProfile, WindowBins, MAX_BINS,
+};
+use crate::utils::group_offsets;
+
+#[derive(Serialize, Deserialize, Default, Clone, Copy, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum PriceByVolumeWeight {
+    #[default]
+    Volume,
+    Tpo,
+    Both,
+}
+
+// Kwargs that override single settings of the profile config, whether it
+// came as `config` or from the old fields.
+#[derive(Serialize, Deserialize, Default)]
+pub struct ProfileOverrides {
+    // price standing for a bin, replaces `center_label`
+    #[serde(default)]
+    label: Option<Label>,
+    // scaling of the binned volume, replaces `pct`
+    #[serde(default)]
+    normalize: Option<Normalize>,
+    #[serde(default)]
+    smoothing: Option<Smoothing>,
+    // inclusive side of the inner bin edges
+    #[serde(default)]
+    closed: Option<Closed>,
+}
+
+// The old kwargs fields, `None` when they were not passed. Without a
+// `config` they spell the profile, with one the fields that were passed
+// override it.
+#[derive(Serialize, Deserialize, Default)]
+pub struct ProfileFields {
+    #[serde(default)]
+    window_size: Option<i32>,
+    #[serde(default)]
+    bins: Option<i32>,
+    #[serde(default)]
+    center_label: Option<bool>,
+    // -1 for no rounding
+    #[serde(default)]
+    round: Option<i32>,
+}
+
+// The profile kwargs every expression shares: the old fields, the unified
+// config they override, the single-setting overrides and the threading.
+#[derive(Serialize, Deserialize, Default)]
+pub struct ProfileKwargs {
+    #[serde(flatten)]
+    fields: ProfileFields,
+    #[serde(default)]
+    config: Option<ProfileConfig>,
+    #[serde(flatten)]
+    overrides: ProfileOverrides,
+    // threading of the rolling kernel or the rows, see `Parallel`
+    #[serde(default)]
+    parallel: Parallel,
+}
+
+#[derive(Serialize, Deserialize)]
 pub struct PriceByVolumeKwargs {
-    window_size: i32,
-    bins: i32,
-    center_label: bool,
+    #[serde(flatten)]
+    profile: ProfileKwargs,
+    #[serde(default)]
+    weight: PriceByVolumeWeight,
+    #[serde(default)]
+    has_by: bool,
+    #[serde(default)]
+    has_period: bool,
+    // number of weight columns after the price, when they were passed as a
+    // list; the profile fields are then named after the inputs
+    #[serde(default)]
+    n_weights: Option<usize>,
+    // keep only the non-empty bins, see `sparse_profile`
+    #[serde(default)]
+    sparse: bool,
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct PriceByVolumeDensifyKwargs {
+    // one bin count for every row, by default each row's `bin_count`
+    #[serde(default)]
+    bins: Option<i32>,
     round: i32,
 }
 
-#[derive(Deserialize)]
+#[derive(Serialize, Deserialize)]
+pub struct PriceByVolumeWeightedKwargs {
+    #[serde(flatten)]
+    profile: ProfileKwargs,
+    pct: bool,
+    signed: bool,
+    has_by: bool,
+}
+
+#[derive(Serialize, Deserialize)]
 pub struct PriceByVolumeTopNKwargs {
-    window_size: i32,
-    bins: i32,
+    #[serde(flatten)]
+    profile: ProfileKwargs,
     n: usize,
-    center_label: bool,
-    round: i32,
     pct: bool,
 }
 
+#[derive(Serialize, Deserialize)]
+pub struct PriceByVolumeListKwargs {
+    #[serde(flatten)]
+    profile: ProfileKwargs,
+    pct: bool,
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct PriceByVolumeMergeKwargs {
+    #[serde(flatten)]
+    profile: ProfileKwargs,
+    tick_size: Option<f64>,
+    pct: bool,
+    // where the labels of the input profiles sit in their bins, independent
+    // of the labels of the merged profile
+    #[serde(default)]
+    input_label: Label,
+    // width of the input bins, by default their label spacing
+    #[serde(default)]
+    input_width: Option<f64>,
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct PriceByVolumeDistanceKwargs {
+    #[serde(flatten)]
+    profile: ProfileKwargs,
+    k: i32,
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct PriceByVolumeStatsKwargs {
+    #[serde(flatten)]
+    profile: ProfileKwargs,
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct PriceByVolumeShapeKwargs {
+    #[serde(flatten)]
+    profile: ProfileKwargs,
+    upper: f64,
+    lower: f64,
+    valley: f64,
+    // share of the range the mass center has to be past for `P` / `b`
+    mass: f64,
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct PriceByVolumePositionKwargs {
+    #[serde(flatten)]
+    profile: ProfileKwargs,
+    // price step of `poc_distance_ticks`, the config's tick size when unset
+    tick_size: Option<f64>,
+    value_area: f64,
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct PriceByVolumeNakedPocKwargs {
+    #[serde(flatten)]
+    profile: ProfileKwargs,
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct PriceByVolumeDevelopingKwargs {
+    #[serde(flatten)]
+    profile: ProfileKwargs,
+    // the config's tick size when unset
+    tick_size: Option<f64>,
+    value_area: f64,
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct PriceByVolumeExcessKwargs {
+    #[serde(flatten)]
+    profile: ProfileKwargs,
+    poor_ratio: f64,
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct PriceByVolumeAggKwargs {
+    #[serde(flatten)]
+    profile: ProfileKwargs,
+    pct: bool,
+    #[serde(default)]
+    weight: PriceByVolumeWeight,
+    #[serde(default)]
+    has_period: bool,
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct PriceByVolumeQuantileKwargs {
+    #[serde(flatten)]
+    profile: ProfileKwargs,
+    quantiles: Vec<f64>,
+    #[serde(default)]
+    interpolation: Interpolation,
+    #[serde(default)]
+    has_by: bool,
+}
+
 // fn price_by_volume_dtype(input_fields: &[Field]) -> PolarsResult<Field> {
 //     let field = Field::new(
 //         "pbv",
@@ -44,248 +238,1728 @@ fn price_by_volume_dtype(input_fields: &[Field]) -> PolarsResult<Field> {
     Ok(Field::new("pbv", DataType::Struct(v)))
 }
 
+fn price_by_volume_weight_dtype(
+    input_fields: &[Field],
+    weight: PriceByVolumeWeight,
+    n_weights: Option<usize>,
+) -> PolarsResult<Field> {
+    let field = price_by_volume_dtype(input_fields)?;
+    let DataType::Struct(mut v) = field.dtype else {
+        unreachable!()
+    };
+    if let Some(n_weights) = n_weights {
+        let names = weight_names(&input_fields[1..=n_weights])?;
+        v.truncate(1);
+        v.extend(
+            names
+                .into_iter()
+                .map(|name| Field::new(name, DataType::List(Box::new(DataType::Float64)))),
+        );
+    }
+    let field_tpo = Field::new("tpo", DataType::List(Box::new(DataType::Float64)));
+    match weight {
+        PriceByVolumeWeight::Volume => {}
+        PriceByVolumeWeight::Tpo => {
+            v.truncate(1);
+            v.push(field_tpo)
+        }
+        PriceByVolumeWeight::Both => v.push(field_tpo),
+    }
+    Ok(Field::new("pbv", DataType::Struct(v)))
+}
+
+fn price_by_volume_kwargs_dtype(
+    input_fields: &[Field],
+    kwargs: PriceByVolumeKwargs,
+) -> PolarsResult<Field> {
+    let field = price_by_volume_weight_dtype(input_fields, kwargs.weight, kwargs.n_weights)?;
+    if kwargs.sparse {
+        Ok(price_by_volume_sparse_dtype(&field))
+    } else {
+        Ok(field)
+    }
+}
+
+fn price_by_volume_agg_dtype(
+    input_fields: &[Field],
+    kwargs: PriceByVolumeAggKwargs,
+) -> PolarsResult<Field> {
+    price_by_volume_weight_dtype(input_fields, kwargs.weight, None)
+}
+
+// Names of the weight columns, which become the struct field names.
+fn weight_names(weight_fields: &[Field]) -> PolarsResult<Vec<&str>> {
+    let mut names: Vec<&str> = Vec::with_capacity(weight_fields.len());
+    for field in weight_fields {
+        let name = field.name().as_str();
+        polars_ensure!(
+            name != "price" && !names.contains(&name),
+            ComputeError: "duplicate or reserved weight column name `{}`, use `.alias` to rename",
+            name
+        );
+        names.push(name);
+    }
+    Ok(names)
+}
+
+// What the kernel bins for `weight`, with the names of the profile fields:
+// the volume columns, `tpo` (one per row, or one per distinct period with a
+// period key) or both.
+struct KernelInput<'a> {
+    price: Vec<f64>,
+    weights: Vec<Vec<f64>>,
+    // distinct periods replace the last weight column, see `rolling_bins`
+    period: Option<Vec<u32>>,
+    names: Vec<&'a str>,
+}
+
+impl<'a> KernelInput<'a> {
+    fn new(
+        price: &Series,
+        volumes: &'a [Series],
+        weight: PriceByVolumeWeight,
+        period: Option<&Series>,
+    ) -> PolarsResult<Self> {
+        let mut weights = vec![];
+        let mut names = vec![];
+        if weight != PriceByVolumeWeight::Tpo {
+            for volume in volumes {
+                weights.push(f64_values(volume)?);
+                names.push(volume.name());
+            }
+        }
+        let period = match weight {
+            PriceByVolumeWeight::Volume => None,
+            _ => {
+                weights.push(vec![1.0; price.len()]);
+                names.push("tpo");
+                period.map(period_codes).transpose()?
+            }
+        };
+        Ok(KernelInput {
+            price: f64_values(price)?,
+            weights,
+            period,
+            names,
+        })
+    }
+
+    // Every window handed to `f`, see `rolling_map`.
+    fn rolling<T: Send>(
+        &self,
+        config: &ProfileConfig,
+        group_offsets: Option<&[usize]>,
+        parallel: Parallel,
+        f: impl Fn(Option<WindowBins>) -> T + Send + Sync,
+    ) -> PolarsResult<Vec<T>> {
+        let weights: Vec<&[f64]> = self.weights.iter().map(|w| w.as_slice()).collect();
+        Ok(on_pool(parallel, |parallel| {
+            rolling_map(
+                &self.price,
+                &weights,
+                self.period.as_deref(),
+                group_offsets,
+                config,
+                parallel,
+                f,
+            )
+        })?)
+    }
+}
+
+// Prices or weights as plain floats for the profile kernel, nulls become NaN
+// which the kernel skips.
+fn f64_values(s: &Series) -> PolarsResult<Vec<f64>> {
+    let s = s.cast(&DataType::Float64)?;
+    Ok(s.f64()?.iter().map(|v| v.unwrap_or(f64::NAN)).collect())
+}
+
+// Number every distinct period value, nulls included, so the kernel can
+// count distinct periods per bin.
+fn period_codes(period: &Series) -> PolarsResult<Vec<u32>> {
+    let mut codes = vec![0u32; period.len()];
+    match period.group_tuples(true, false)? {
+        GroupsProxy::Idx(groups) => {
+            for (code, (_, rows)) in groups.iter().enumerate() {
+                for &row in rows.iter() {
+                    codes[row as usize] = code as u32;
+                }
+            }
+        }
+        GroupsProxy::Slice { groups, .. } => {
+            for (code, [first, len]) in groups.iter().enumerate() {
+                codes[*first as usize..(first + len) as usize].fill(code as u32);
+            }
+        }
+    }
+    Ok(codes)
+}
+
+impl From<ConfigError> for PolarsError {
+    fn from(e: ConfigError) -> Self {
+        polars_err!(ComputeError: "{}", e)
+    }
+}
+
+// The kwargs use -1 for no rounding.
+fn round_decimals(round: i32) -> Option<u32> {
+    (round >= 0).then_some(round as u32)
+}
+
+fn label_of(center_label: bool) -> Label {
+    if center_label {
+        Label::Center
+    } else {
+        Label::Lower
+    }
+}
+
+// The config spelled by the old kwargs fields.
+fn profile_config(fields: &ProfileFields, normalize: Normalize) -> ProfileConfig {
+    let window_size = fields.window_size.unwrap_or(0).max(0) as usize;
+    let bins = fields.bins.unwrap_or(0).max(0) as usize;
+    let config = ProfileConfig::new(window_size, bins)
+        .with_label(label_of(fields.center_label.unwrap_or(true)))
+        .with_normalize(normalize);
+    match fields.round.and_then(round_decimals) {
+        Some(round) => config.with_round(round),
+        None => config,
+    }
+}
+
+impl ProfileKwargs {
+    // The unified config when one was passed, else the one from the old
+    // fields. Old fields that were passed alongside a config override it.
+    // `normalize` is the scaling asked for by `pct` / `signed`, it applies
+    // when the config leaves `normalize` at `None`, and the `overrides` win
+    // over all of them.
+    fn config(&self, normalize: Normalize) -> PolarsResult<ProfileConfig> {
+        let fields = &self.fields;
+        let mut config = match &self.config {
+            Some(config) => {
+                let mut config = config.clone();
+                if let Some(window_size) = fields.window_size {
+                    config.window_size = window_size.max(0) as usize;
+                }
+                if let Some(bins) = fields.bins {
+                    config.binning = Binning::Bins(bins.max(0) as usize);
+                }
+                if let Some(center_label) = fields.center_label {
+                    config.label = label_of(center_label);
+                }
+                if let Some(round) = fields.round {
+                    config.round = round_decimals(round);
+                }
+                if config.normalize == Normalize::None {
+                    config.normalize = normalize;
+                }
+                config
+            }
+            None => profile_config(fields, normalize),
+        };
+        let overrides = &self.overrides;
+        if let Some(label) = overrides.label {
+            config.label = label;
+        }
+        if let Some(normalize) = overrides.normalize {
+            config.normalize = normalize;
+        }
+        if let Some(smoothing) = overrides.smoothing {
+            config.smoothing = smoothing;
+        }
+        if let Some(closed) = overrides.closed {
+            config.closed = closed;
+        }
+        config.validate()?;
+        Ok(config)
+    }
+
+    // `config` when one was passed, for the expressions that only take some
+    // of its settings and do not need the old fields otherwise.
+    fn passed_config(&self) -> PolarsResult<Option<ProfileConfig>> {
+        self.config
+            .is_some()
+            .then(|| self.config(Normalize::None))
+            .transpose()
+    }
+}
+
+// Whether this thread is a worker of a polars pool. The plugin links its own
+// copy of rayon and cannot see the pool polars runs the query on, but polars
+// names its workers `<POLARS_THREAD_NAME>-<i>`, "polars-0" and so on, and so
+// does the plugin's copy of the pool.
+fn on_polars_worker() -> bool {
+    let prefix = std::env::var("POLARS_THREAD_NAME").unwrap_or_else(|_| "polars".to_string());
+    std::thread::current()
+        .name()
+        .and_then(|name| name.strip_prefix(prefix.as_str()))
+        .and_then(|rest| rest.strip_prefix('-'))
+        .is_some_and(|i| i.parse::<usize>().is_ok())
+}
+
+// Run `f` on the plugin's own copy of the polars pool. It is sized by
+// `POLARS_MAX_THREADS` like the pool polars runs the query on, but it is not
+// that pool. Polars evaluates a single expression of a `select` on the calling
+// thread and runs several expressions, `group_by` groups and `.over` windows
+// on its workers, so `Auto` on a polars worker, or on any other rayon worker,
+// stays serial rather than filling the machine a second time.
+fn on_pool<T: Send>(parallel: Parallel, f: impl FnOnce(Parallel) -> T + Send) -> T {
+    let parallel = match parallel {
+        Parallel::Auto if on_polars_worker() || rayon::current_thread_index().is_some() => {
+            Parallel::Off
+        }
+        parallel => parallel,
+    };
+    POOL.install(|| f(parallel))
+}
+
+// The metrics look at rolling windows, an empty one has no profile to
+// describe.
+fn ensure_window(config: &ProfileConfig) -> PolarsResult<()> {
+    polars_ensure!(
+        config.window_size > 0,
+        ComputeError: "`window_size` must be positive, got {}", config.window_size
+    );
+    Ok(())
+}
+
+// The rolling kernel for the metrics: the grid and the binned weights of
+// every window, neither rounded nor normalized.
+fn metric_bins(
+    price: &[f64],
+    weights: &[&[f64]],
+    config: &ProfileConfig,
+    parallel: Parallel,
+) -> PolarsResult<RollingBins> {
+    let mut raw = config.clone();
+    raw.round = None;
+    raw.normalize = Normalize::None;
+    Ok(on_pool(parallel, |parallel| {
+        rolling_bins(price, weights, None, None, &raw, parallel)
+    })?)
+}
+
+// The ticks of the window ending at `row`.
+fn window_of(values: &[f64], row: usize, window_size: usize) -> &[f64] {
+    &values[row + 1 - window_size..=row]
+}
+
+// Rolling profiles as a struct of a `price` list and one list per weight,
+// named after the weight series, see `KernelInput`.
 fn price_by_volume(
-    price: &Series,
-    volume: &Series,
-    window_size: i32,
-    bins: i32,
-    center_label: bool,
-    round: i32,
-    pct: bool,
+    input: &KernelInput,
+    config: &ProfileConfig,
+    group_offsets: Option<&[usize]>,
+    parallel: Parallel,
 ) -> PolarsResult<Series> {
-    let window_size = window_size as usize;
-    let mut pbv = vec![];
-    let mut label = vec![];
-    for i in 1..(price.len() + 1) {
-        // println!("i: {}", i);
-        if i < window_size {
-            pbv.push(None);
-            label.push(None);
+    let rows = input.rolling(config, group_offsets, parallel, |row| row)?;
+    let label: Vec<Option<Series>> = rows
+        .iter()
+        .map(|row| row.as_ref().map(|row| Series::new("price", &row.labels)))
+        .collect();
+    let mut fields = vec![Series::new("price", &label)];
+    for (k, name) in input.names.iter().enumerate() {
+        let pbv_k: Vec<Option<Series>> = rows
+            .iter()
+            .map(|row| row.as_ref().map(|row| Series::new(name, &row.binned[k])))
+            .collect();
+        fields.push(Series::new(name, &pbv_k));
+    }
+    let out = StructChunked::new("pbv", &fields)?;
+    Ok(out.into_series())
+}
+
+#[polars_expr(output_type_func=price_by_volume_dtype)]
+fn pbv_not_par(inputs: &[Series], kwargs: PriceByVolumeKwargs) -> PolarsResult<Series> {
+    let price = &inputs[0].to_float()?;
+    let mut volume = inputs[1].to_float()?;
+    volume.rename("volume");
+    let config = kwargs.profile.config(Normalize::None)?;
+    let volumes = [volume];
+    let input = KernelInput::new(price, &volumes, PriceByVolumeWeight::Volume, None)?;
+    price_by_volume(&input, &config, None, Parallel::Off)
+}
+
+// The optional inputs come after price and the weight columns in this order:
+// `by`, `period`. A single weight column keeps the `volume` field name.
+fn price_by_volume_rolling(
+    inputs: &[Series],
+    kwargs: &PriceByVolumeKwargs,
+    normalize: Normalize,
+) -> PolarsResult<Series> {
+    let price = &inputs[0].to_float()?;
+    let n_weights = kwargs.n_weights.unwrap_or(1);
+    let mut volumes = inputs[1..=n_weights]
+        .iter()
+        .map(|v| v.to_float())
+        .collect::<PolarsResult<Vec<Series>>>()?;
+    if kwargs.n_weights.is_none() {
+        volumes[0].rename("volume");
+    } else {
+        let fields: Vec<Field> = volumes.iter().map(|v| v.field().into_owned()).collect();
+        weight_names(&fields)?;
+    }
+    let offsets = if kwargs.has_by {
+        Some(group_offsets(&inputs[1 + n_weights])?)
+    } else {
+        None
+    };
+    let period = kwargs
+        .has_period
+        .then(|| &inputs[1 + n_weights + kwargs.has_by as usize]);
+    let config = kwargs.profile.config(normalize)?;
+    let input = KernelInput::new(price, &volumes, kwargs.weight, period)?;
+    if kwargs.sparse {
+        sparse_profile(&input, &config, offsets.as_deref(), kwargs.profile.parallel)
+    } else {
+        price_by_volume(&input, &config, offsets.as_deref(), kwargs.profile.parallel)
+    }
+}
+
+#[polars_expr(output_type_func_with_kwargs=price_by_volume_kwargs_dtype)]
+fn pbv(inputs: &[Series], kwargs: PriceByVolumeKwargs) -> PolarsResult<Series> {
+    price_by_volume_rolling(inputs, &kwargs, Normalize::None)
+}
+
+#[polars_expr(output_type_func_with_kwargs=price_by_volume_kwargs_dtype)]
+fn pbv_pct(inputs: &[Series], kwargs: PriceByVolumeKwargs) -> PolarsResult<Series> {
+    price_by_volume_rolling(inputs, &kwargs, Normalize::Sum)
+}
+
+// The dense struct of lists becomes a struct per row of its bin count, the
+// label grid of its empty bins and the list of its non-empty bins, each with
+// its bin number in front of the price and weight fields.
+fn price_by_volume_sparse_dtype(dense: &Field) -> Field {
+    let DataType::Struct(fields) = &dense.dtype else {
+        unreachable!()
+    };
+    let mut bin = vec![Field::new("bin_index", DataType::UInt32)];
+    bin.extend(
+        fields
+            .iter()
+            .map(|f| Field::new(f.name(), DataType::Float64)),
+    );
+    let v = vec![
+        Field::new("bin_count", DataType::UInt32),
+        Field::new("label_start", DataType::Float64),
+        Field::new("label_step", DataType::Float64),
+        Field::new("bins", DataType::List(Box::new(DataType::Struct(bin)))),
+    ];
+    Field::new(dense.name(), DataType::Struct(v))
+}
+
+// The non-empty bins of one window, see `sparse_profile`.
+struct SparseRow {
+    bin_count: u32,
+    label_start: f64,
+    label_step: f64,
+    bin_index: Vec<u32>,
+    labels: Vec<f64>,
+    binned: Vec<Vec<f64>>,
+}
+
+// Bin `n` of a grid is labelled `label_start + n * label_step` when it is
+// empty, see `Grid::labels`; a `Vwap` bin without ticks takes its center.
+fn label_grid(grid: &Grid, label: Label) -> (f64, f64) {
+    match label {
+        Label::Lower => (grid.min, grid.interval),
+        Label::Center | Label::Vwap => (grid.min + grid.interval / 2.0, grid.interval),
+        Label::Upper => (grid.min + grid.interval, grid.interval),
+        Label::Index => (0.0, 1.0),
+    }
+}
+
+// Keep the bins of a window where any weight is non-zero.
+fn sparse_row(row: WindowBins, label: Label) -> SparseRow {
+    let (label_start, label_step) = label_grid(&row.grid, label);
+    let bin_index: Vec<u32> = (0..row.labels.len())
+        .filter(|&n| row.binned.iter().any(|w| w[n] != 0.0))
+        .map(|n| n as u32)
+        .collect();
+    let keep =
+        |values: &[f64]| -> Vec<f64> { bin_index.iter().map(|&n| values[n as usize]).collect() };
+    SparseRow {
+        bin_count: row.labels.len() as u32,
+        label_start,
+        label_step,
+        labels: keep(&row.labels),
+        binned: row.binned.iter().map(|w| keep(w)).collect(),
+        bin_index,
+    }
+}
+
+// A list column from the values of every row laid end to end and the offsets
+// of the rows into them.
+fn list_from_flat(
+    name: &str,
+    values: &Series,
+    offsets: Vec<i64>,
+    validity: Vec<bool>,
+) -> PolarsResult<Series> {
+    let values = values.rechunk().to_arrow(0, true);
+    let dtype = ArrowDataType::LargeList(Box::new(ArrowField::new(
+        "item",
+        values.data_type().clone(),
+        true,
+    )));
+    let offsets = OffsetsBuffer::try_from(offsets)?;
+    let array = ListArray::<i64>::try_new(dtype, offsets, values, Some(validity.into()))?;
+    Series::try_from((name, Box::new(array) as ArrayRef))
+}
+
+// Rolling profiles keeping only the bins where any weight is non-zero. With
+// thousands of mostly empty bins this is far smaller than the dense lists,
+// and every window is cut down as soon as it is binned, so the dense lists
+// are never held. Every row carries its own bin count and label grid, which
+// differ between the rows of a tick size grid, for `densify_profile`.
+fn sparse_profile(
+    input: &KernelInput,
+    config: &ProfileConfig,
+    group_offsets: Option<&[usize]>,
+    parallel: Parallel,
+) -> PolarsResult<Series> {
+    let rows = input.rolling(config, group_offsets, parallel, |row| {
+        row.map(|row| sparse_row(row, config.label))
+    })?;
+    let kept: usize = rows.iter().flatten().map(|row| row.bin_index.len()).sum();
+    let mut bin_index: Vec<u32> = Vec::with_capacity(kept);
+    let mut labels: Vec<f64> = Vec::with_capacity(kept);
+    let mut binned: Vec<Vec<f64>> = vec![Vec::with_capacity(kept); input.names.len()];
+    // rows without a window are null and repeat the offset before them
+    let mut offsets: Vec<i64> = Vec::with_capacity(rows.len() + 1);
+    offsets.push(0);
+    for row in rows.iter() {
+        if let Some(row) = row {
+            bin_index.extend(&row.bin_index);
+            labels.extend(&row.labels);
+            for (column, w) in binned.iter_mut().zip(&row.binned) {
+                column.extend(w);
+            }
+        }
+        offsets.push(bin_index.len() as i64);
+    }
+    let mut bin_fields = vec![
+        Series::new("bin_index", bin_index),
+        Series::new("price", labels),
+    ];
+    for (name, column) in input.names.iter().zip(binned) {
+        bin_fields.push(Series::new(name, column));
+    }
+    let bins = StructChunked::new("bins", &bin_fields)?.into_series();
+    let validity = rows.iter().map(Option::is_some).collect();
+    let field = |f: fn(&SparseRow) -> f64| -> Float64Chunked {
+        rows.iter().map(|row| row.as_ref().map(f)).collect()
+    };
+    let bin_count: UInt32Chunked = rows
+        .iter()
+        .map(|row| row.as_ref().map(|row| row.bin_count))
+        .collect();
+    let out = StructChunked::new(
+        "pbv",
+        &[
+            bin_count.with_name("bin_count").into_series(),
+            field(|row| row.label_start)
+                .with_name("label_start")
+                .into_series(),
+            field(|row| row.label_step)
+                .with_name("label_step")
+                .into_series(),
+            list_from_flat("bins", &bins, offsets, validity)?,
+        ],
+    )?;
+    Ok(out.into_series())
+}
+
+// The fields of a sparse bin, after `bin_index`: `price` and the weights.
+fn sparse_bin_fields(dtype: &DataType) -> PolarsResult<&[Field]> {
+    if let DataType::Struct(fields) = dtype {
+        if let Some(DataType::List(inner)) =
+            fields.iter().find(|f| f.name() == "bins").map(|f| &f.dtype)
+        {
+            if let DataType::Struct(bin) = inner.as_ref() {
+                return Ok(&bin[1..]);
+            }
+        }
+    }
+    polars_bail!(ComputeError: "pbv_densify expects the output of pbv(..., sparse=True)")
+}
+
+fn price_by_volume_densify_dtype(input_fields: &[Field]) -> PolarsResult<Field> {
+    let list_f64 = DataType::List(Box::new(DataType::Float64));
+    let v: Vec<Field> = sparse_bin_fields(&input_fields[0].dtype)?
+        .iter()
+        .map(|f| Field::new(f.name(), list_f64.clone()))
+        .collect();
+    Ok(Field::new(input_fields[0].name(), DataType::Struct(v)))
+}
+
+// Expand the sparse bins back to dense bins, `bins` of them or else the
+// `bin_count` of every row. Empty bins get zero weight and the price of their
+// row's label grid, rounded to `round`.
+fn densify_profile(sparse: &Series, bins: Option<i32>, round: i32) -> PolarsResult<Series> {
+    let fields = sparse_bin_fields(sparse.dtype())?.to_vec();
+    if let Some(bins) = bins {
+        polars_ensure!(bins > 0, ComputeError: "`bins` must be positive, got {}", bins);
+    }
+    let sparse = sparse.struct_()?;
+    let bin_count = sparse.field_by_name("bin_count")?.cast(&DataType::UInt32)?;
+    let bin_count = bin_count.u32()?;
+    let label_start = sparse
+        .field_by_name("label_start")?
+        .cast(&DataType::Float64)?;
+    let label_start = label_start.f64()?;
+    let label_step = sparse
+        .field_by_name("label_step")?
+        .cast(&DataType::Float64)?;
+    let label_step = label_step.f64()?;
+    let rows = sparse.field_by_name("bins")?;
+    let mut columns: Vec<Vec<Option<Series>>> = vec![Vec::with_capacity(rows.len()); fields.len()];
+    for (i, row) in rows.list()?.into_iter().enumerate() {
+        let (Some(row), Some(count), Some(start), Some(step)) =
+            (row, bin_count.get(i), label_start.get(i), label_step.get(i))
+        else {
+            columns.iter_mut().for_each(|c| c.push(None));
+            continue;
+        };
+        let row = row.struct_()?;
+        let bin_index: Vec<usize> = row.fields()[0]
+            .cast(&DataType::UInt32)?
+            .u32()?
+            .into_no_null_iter()
+            .map(|k| k as usize)
+            .collect();
+        let bins = bins.map_or(count as usize, |bins| bins as usize);
+        polars_ensure!(
+            bin_index.iter().all(|&k| k < bins),
+            ComputeError: "bin_index out of range for {} bins", bins
+        );
+        let mut price: Vec<f64> = (0..bins)
+            .map(|k| round_to(start + k as f64 * step, round_decimals(round)))
+            .collect();
+        let known_price = row.fields()[1].cast(&DataType::Float64)?;
+        for (&k, p) in bin_index.iter().zip(known_price.f64()?.into_no_null_iter()) {
+            price[k] = p;
+        }
+        columns[0].push(Some(Series::new("price", &price)));
+        for (column, field) in columns[1..].iter_mut().zip(&row.fields()[2..]) {
+            let mut weight = vec![0.0; bins];
+            let known = field.cast(&DataType::Float64)?;
+            for (&k, w) in bin_index.iter().zip(known.f64()?.into_no_null_iter()) {
+                weight[k] = w;
+            }
+            column.push(Some(Series::new(field.name(), &weight)));
+        }
+    }
+    let out: Vec<Series> = columns
+        .iter()
+        .zip(&fields)
+        .map(|(c, f)| Series::new(f.name(), c))
+        .collect();
+    let out = StructChunked::new(sparse.name(), &out)?;
+    Ok(out.into_series())
+}
+
+#[polars_expr(output_type_func=price_by_volume_densify_dtype)]
+fn pbv_densify(inputs: &[Series], kwargs: PriceByVolumeDensifyKwargs) -> PolarsResult<Series> {
+    densify_profile(&inputs[0], kwargs.bins, kwargs.round)
+}
+
+fn price_by_volume_weighted_dtype(input_fields: &[Field]) -> PolarsResult<Field> {
+    let v: Vec<Field> = vec![
+        Field::new(
+            "price",
+            DataType::List(Box::new(input_fields[0].dtype.clone())),
+        ),
+        Field::new("weight", DataType::List(Box::new(DataType::Float64))),
+    ];
+    Ok(Field::new("pbv", DataType::Struct(v)))
+}
+
+// Same profile as `pbv` with any numeric weight (notional, trade count, open
+// interest change, a model score, ...) in place of the volume. Negative
+// weights are summed as they are.
+#[polars_expr(output_type_func=price_by_volume_weighted_dtype)]
+fn pbv_weighted(inputs: &[Series], kwargs: PriceByVolumeWeightedKwargs) -> PolarsResult<Series> {
+    let price = &inputs[0].to_float()?;
+    let mut weight = inputs[1].cast(&DataType::Float64)?;
+    weight.rename("weight");
+    let offsets = if kwargs.has_by {
+        Some(group_offsets(&inputs[2])?)
+    } else {
+        None
+    };
+    let config = kwargs.profile.config(Normalize::from_pct(kwargs.pct, kwargs.signed))?;
+    let weights = [weight];
+    let input = KernelInput::new(price, &weights, PriceByVolumeWeight::Volume, None)?;
+    price_by_volume(&input, &config, offsets.as_deref(), kwargs.profile.parallel)
+}
+
+#[polars_expr(output_type_func_with_kwargs=price_by_volume_agg_dtype)]
+fn pbv_agg(inputs: &[Series], kwargs: PriceByVolumeAggKwargs) -> PolarsResult<Series> {
+    let price = &inputs[0].to_float()?;
+    let volume = &inputs[1].to_float()?;
+    if price.is_empty() {
+        let fields = [price.field().into_owned(), volume.field().into_owned()];
+        let dtype = price_by_volume_weight_dtype(&fields, kwargs.weight, None)?;
+        return Ok(Series::full_null("pbv", 1, dtype.data_type()));
+    }
+    let period = kwargs.has_period.then(|| &inputs[2]);
+    // one window over the whole input, the profile lands on the last row.
+    // polars already runs the groups in parallel, so stay on the serial kernel.
+    let volumes = [volume.clone().with_name("volume")];
+    let config = kwargs.profile.config(Normalize::from_pct(kwargs.pct, false))?
+    .with_window_size(price.len());
+    let input = KernelInput::new(price, &volumes, kwargs.weight, period)?;
+    let out = price_by_volume(&input, &config, None, Parallel::Off)?;
+    Ok(out.tail(Some(1)))
+}
+
+fn price_by_volume_f64_dtype(_input_fields: &[Field]) -> PolarsResult<Field> {
+    let list_f64 = DataType::List(Box::new(DataType::Float64));
+    let v: Vec<Field> = vec![
+        Field::new("price", list_f64.clone()),
+        Field::new("volume", list_f64),
+    ];
+    Ok(Field::new("pbv", DataType::Struct(v)))
+}
+
+#[polars_expr(output_type_func=price_by_volume_f64_dtype)]
+fn pbv_list(inputs: &[Series], kwargs: PriceByVolumeListKwargs) -> PolarsResult<Series> {
+    let list_f64 = DataType::List(Box::new(DataType::Float64));
+    let price = inputs[0].cast(&list_f64)?;
+    let volume = inputs[1].cast(&list_f64)?;
+    let price = price.list()?;
+    let volume = volume.list()?;
+    let config = kwargs
+        .profile
+        .config(Normalize::from_pct(kwargs.pct, false))?;
+    let mut labels = Vec::with_capacity(price.len());
+    let mut volumes = Vec::with_capacity(price.len());
+    // SAFETY: the amortized series are only read inside the loop
+    let rows = unsafe { price.amortized_iter().zip(volume.amortized_iter()) };
+    for (idx, (window_price, window_volume)) in rows.enumerate() {
+        let profile = match (window_price, window_volume) {
+            (Some(window_price), Some(window_volume)) => {
+                let window_price = f64_values(window_price.as_ref())?;
+                let window_volume = f64_values(window_volume.as_ref())?;
+                polars_ensure!(
+                    window_price.len() == window_volume.len(),
+                    ShapeMismatch: "row {} has {} prices but {} volumes",
+                    idx, window_price.len(), window_volume.len()
+                );
+                VolumeProfile::from_window(&window_price, &window_volume, &config)?
+            }
+            _ => None,
+        };
+        let (label, volume) = profile.map(|p| (p.price, p.volume)).unzip();
+        labels.push(label.map(|l| Series::new("price", l).to_arrow(0, true)));
+        volumes.push(volume.map(|v| Series::new("volume", v).to_arrow(0, true)));
+    }
+    let label: ListChunked = labels
+        .into_iter()
+        .collect_ca_with_dtype("price", list_f64.clone());
+    let pbv: ListChunked = volumes
+        .into_iter()
+        .collect_ca_with_dtype("volume", list_f64);
+    let out = StructChunked::new("pbv", &[label.into_series(), pbv.into_series()])?;
+    Ok(out.into_series())
+}
+
+// Re-bin a set of profiles onto one common grid. Every source bin spreads its
+// volume over the target bins it overlaps, in proportion to the overlap.
+// `input_label` is where the labels of the sources sit in their bins. The
+// bins of a source are `input_width` wide, else as wide as its label spacing
+// over the whole profile, so rounded labels do not skew the width; a single
+// bin without `input_width` is a point.
+fn merge_profiles(
+    profiles: &[(Vec<f64>, Vec<f64>)],
+    binning: Binning,
+    input_label: Label,
+    input_width: Option<f64>,
+    label: Label,
+) -> PolarsResult<(Vec<f64>, Vec<f64>, f64)> {
+    // (lower bound, width, volume) of every source bin
+    let mut source_bins = vec![];
+    for (source_label, volume) in profiles {
+        let bins = source_label.len();
+        let width = input_width.unwrap_or(if bins > 1 {
+            (source_label[bins - 1] - source_label[0]) / (bins - 1) as f64
         } else {
-            let mut volume_at_price = vec![];
-            let mut price_label = vec![];
-            let start = (i - window_size) as i64;
-            let window_price = price.slice(start, window_size);
-            let window_volume = volume.slice(start, window_size);
-            let max_price: f64 = window_price.max()?.unwrap();
-            let min_price: f64 = window_price.min()?.unwrap();
-            let range = max_price - min_price;
-            let interval = range / bins as f64;
-            for n in 0..bins {
-                let lower_bound = min_price + n as f64 * interval;
-                let upper_bound = min_price + (n + 1) as f64 * interval;
-                let center = (lower_bound + upper_bound) / 2.0;
-                if n == bins - 1 {
-                    // println!("start: {}, lower: {}", start, lower_bound);
-                    let v: f64 = window_volume
-                        .filter(&window_price.gt_eq(lower_bound)?)?
-                        .sum()?;
-                    volume_at_price.push(v);
-                } else {
-                    // println!(
-                    //     "start: {}, lower: {}, upper: {}",
-                    //     start, lower_bound, upper_bound
-                    // );
-                    let mask = window_price.gt_eq(lower_bound)? & window_price.lt(upper_bound)?;
-                    let v = window_volume.filter(&mask)?.sum()?;
-                    volume_at_price.push(v);
-                }
-                if center_label {
-                    price_label.push(center);
-                } else {
-                    price_label.push(lower_bound);
-                }
+            0.0
+        });
+        let first_lower = match input_label {
+            Label::Center => source_label[0] - width / 2.0,
+            Label::Upper => source_label[0] - width,
+            _ => source_label[0],
+        };
+        for (n, v) in volume.iter().enumerate() {
+            source_bins.push((first_lower + n as f64 * width, width, *v));
+        }
+    }
+    let min_price = source_bins
+        .iter()
+        .map(|b| b.0)
+        .fold(f64::INFINITY, f64::min);
+    let max_price = source_bins
+        .iter()
+        .map(|b| b.0 + b.1)
+        .fold(f64::NEG_INFINITY, f64::max);
+
+    let (start, interval, n) = match binning {
+        Binning::Bins(bins) => (min_price, (max_price - min_price) / bins as f64, bins),
+        Binning::TickSize(tick_size) => {
+            let start = (min_price / tick_size).floor() * tick_size;
+            let n = ((max_price - start) / tick_size).ceil().max(1.0);
+            polars_ensure!(
+                n <= MAX_BINS as f64,
+                ComputeError: "`tick_size` {} gives {} bins, more than {}", tick_size, n, MAX_BINS
+            );
+            let n = n as usize;
+            (start, tick_size, n)
+        }
+    };
+
+    let mut volume_at_price = vec![0.0; n];
+    for (lower_bound, width, v) in source_bins {
+        let first = if interval > 0.0 {
+            (((lower_bound - start) / interval).floor().max(0.0) as usize).min(n - 1)
+        } else {
+            0
+        };
+        if width <= 0.0 || interval <= 0.0 {
+            volume_at_price[first] += v;
+            continue;
+        }
+        let upper_bound = lower_bound + width;
+        let last = (((upper_bound - start) / interval).ceil() as usize).clamp(first + 1, n);
+        let overlaps: Vec<f64> = (first..last)
+            .map(|k| {
+                let bin_lower = start + k as f64 * interval;
+                let bin_upper = bin_lower + interval;
+                (upper_bound.min(bin_upper) - lower_bound.max(bin_lower)).max(0.0)
+            })
+            .collect();
+        // share by the overlap actually found so no volume is lost to float error
+        let total_overlap: f64 = overlaps.iter().sum();
+        if total_overlap <= 0.0 {
+            volume_at_price[first] += v;
+            continue;
+        }
+        for (k, overlap) in (first..last).zip(overlaps) {
+            volume_at_price[k] += v * overlap / total_overlap;
+        }
+    }
+    let price_label = (0..n)
+        .map(|k| {
+            let lower_bound = start + k as f64 * interval;
+            match label {
+                Label::Center => lower_bound + interval / 2.0,
+                Label::Upper => lower_bound + interval,
+                _ => lower_bound,
+            }
+        })
+        .collect();
+    Ok((price_label, volume_at_price, interval))
+}
+
+// The grid, labels, rounding and scaling of a merge: the config when one was
+// passed, with the fields that were passed on top.
+fn merge_config(kwargs: &PriceByVolumeMergeKwargs) -> PolarsResult<ProfileConfig> {
+    let fields = &kwargs.profile.fields;
+    let mut config = kwargs
+        .profile
+        .config
+        .clone()
+        .unwrap_or_else(|| ProfileConfig::new(0, 0));
+    match (fields.bins, kwargs.tick_size) {
+        (Some(bins), None) => config.binning = Binning::Bins(bins.max(0) as usize),
+        (None, Some(tick_size)) => config.binning = Binning::TickSize(tick_size),
+        (None, None) if kwargs.profile.config.is_some() => {}
+        _ => polars_bail!(ComputeError: "exactly one of `bins` and `tick_size` must be set"),
+    }
+    if let Some(center_label) = fields.center_label {
+        config.label = label_of(center_label);
+    }
+    if let Some(round) = fields.round {
+        config.round = round_decimals(round);
+    }
+    if kwargs.pct && config.normalize == Normalize::None {
+        config.normalize = Normalize::Sum;
+    }
+    if let Some(label) = kwargs.profile.overrides.label {
+        config.label = label;
+    }
+    if let Some(normalize) = kwargs.profile.overrides.normalize {
+        config.normalize = normalize;
+    }
+    // the source bins are read back from their labels, and the merged bins
+    // have no ticks for `Vwap`
+    for label in [kwargs.input_label, config.label] {
+        polars_ensure!(
+            matches!(label, Label::Lower | Label::Center | Label::Upper),
+            ComputeError: "`pbv_merge` takes `lower`, `center` or `upper` labels, got {:?}", label
+        );
+    }
+    if let Some(width) = kwargs.input_width {
+        polars_ensure!(
+            width.is_finite() && width > 0.0,
+            ComputeError: "`input_width` must be positive, got {}", width
+        );
+    }
+    config.validate()?;
+    Ok(config)
+}
+
+#[polars_expr(output_type_func=price_by_volume_f64_dtype)]
+fn pbv_merge(inputs: &[Series], kwargs: PriceByVolumeMergeKwargs) -> PolarsResult<Series> {
+    let config = merge_config(&kwargs)?;
+    let list_f64 = DataType::List(Box::new(DataType::Float64));
+    let pbv = inputs[0].struct_()?;
+    let price = pbv.field_by_name("price")?.cast(&list_f64)?;
+    let volume = pbv.field_by_name("volume")?.cast(&list_f64)?;
+    let mut profiles = vec![];
+    for (label, v) in price.list()?.into_iter().zip(volume.list()?.into_iter()) {
+        if let (Some(label), Some(v)) = (label, v) {
+            let (label, v): (Vec<f64>, Vec<f64>) = label
+                .f64()?
+                .into_iter()
+                .zip(v.f64()?.into_iter())
+                .filter_map(|(l, v)| Some((l?, v?)))
+                .unzip();
+            if !label.is_empty() {
+                profiles.push((label, v));
             }
-            let pbv_s = Series::new("volume", &volume_at_price);
-            let pbv_s = if pct {
-                let total_volume: f64 = pbv_s.sum()?;
-                if round >= 0 {
-                    (pbv_s / total_volume).round(round as u32)?
-                } else {
-                    pbv_s / total_volume
-                }
-            } else if round >= 0 {
-                pbv_s.round(round as u32)?
-            } else {
-                pbv_s
-            };
-            pbv.push(Some(pbv_s));
-            let price_label_s = if round < 0 {
-                Series::new("price", &price_label)
-            } else {
-                Series::new("price", &price_label).round(round as u32)?
-            };
-            label.push(Some(price_label_s));
         }
     }
-    let label_series = Series::new("price", &label);
-    let pbv_series = Series::new("volume", &pbv);
+    if profiles.is_empty() {
+        let dtype = price_by_volume_f64_dtype(&[])?;
+        return Ok(Series::full_null("pbv", 1, dtype.data_type()));
+    }
+    let (price_label, mut volume_at_price, interval) = merge_profiles(
+        &profiles,
+        config.binning,
+        kwargs.input_label,
+        kwargs.input_width,
+        config.label,
+    )?;
+    let round = config.round;
+    let price_label: Vec<f64> = price_label
+        .into_iter()
+        .map(|p| round_to(p, round))
+        .collect();
+    normalize(&mut volume_at_price, config.normalize, interval, round);
+    let price_label_s = Series::new("price", &price_label);
+    let pbv_s = Series::new("volume", &volume_at_price);
+    let label_series = Series::new("price", &[price_label_s]);
+    let pbv_series = Series::new("volume", &[pbv_s]);
     let out = StructChunked::new("pbv", &[label_series, pbv_series])?;
     Ok(out.into_series())
 }
 
-fn price_by_volume_par(
-    price: &Series,
-    volume: &Series,
-    window_size: i32,
-    bins: i32,
-    center_label: bool,
-    round: i32,
-    pct: bool,
+const DISTANCE_FIELDS: [&str; 4] = ["wasserstein", "kl", "js", "poc_shift"];
+
+fn f64_struct_dtype(name: &str, fields: &[&str]) -> Field {
+    let v: Vec<Field> = fields
+        .iter()
+        .map(|field| Field::new(field, DataType::Float64))
+        .collect();
+    Field::new(name, DataType::Struct(v))
+}
+
+// Build one Float64 field per metric from an optional row of metrics per input row.
+fn f64_fields_from_rows<const N: usize>(
+    fields: &[&str; N],
+    rows: &[Option<[f64; N]>],
+) -> Vec<Series> {
+    fields
+        .iter()
+        .enumerate()
+        .map(|(idx, field)| {
+            let values: Float64Chunked = rows.iter().map(|row| row.map(|row| row[idx])).collect();
+            values.with_name(field).into_series()
+        })
+        .collect()
+}
+
+fn f64_struct_from_rows<const N: usize>(
+    name: &str,
+    fields: &[&str; N],
+    rows: &[Option<[f64; N]>],
 ) -> PolarsResult<Series> {
-    let window_size = window_size as usize;
-    let price_len = price.len();
-    let thread_count = rayon::current_num_threads() * 64; // for small chunk size
-    let chunk_size = (price_len + thread_count - 1) / thread_count;
-
-    let pbv: Vec<Option<Series>> = (0..thread_count)
-        .into_par_iter()
-        .flat_map(|thread_idx| {
-            let start_idx = thread_idx * chunk_size + 1;
-            let end_idx = ((thread_idx + 1) * chunk_size + 1).min(price_len + 1);
-            (start_idx..end_idx).map(|i| {
-                if i < window_size {
-                    None
-                } else {
-                    let start = (i - window_size) as i64;
-                    let window_price = price.slice(start, window_size);
-                    let window_volume = volume.slice(start, window_size);
-                    let max_price: f64 = window_price.max().unwrap().unwrap();
-                    let min_price: f64 = window_price.min().unwrap().unwrap();
-                    let range = max_price - min_price;
-                    let interval = range / bins as f64;
-    
-                    let volume_at_price: Vec<f64> = (0..bins)
-                        .into_par_iter()
-                        .map(|n| {
-                            let lower_bound = min_price + n as f64 * interval;
-                            let upper_bound = min_price + (n + 1) as f64 * interval;
-                            if n == bins - 1 {
-                                window_volume
-                                    .filter(&window_price.gt_eq(lower_bound).unwrap())
-                                    .unwrap()
-                                    .sum()
-                                    .unwrap()
-                            } else {
-                                let mask = window_price.gt_eq(lower_bound).unwrap()
-                                    & window_price.lt(upper_bound).unwrap();
-                                window_volume.filter(&mask).unwrap().sum().unwrap()
-                            }
-                        })
-                        .collect();
-    
-                    let pbv_s = Series::new("volume", &volume_at_price);
-                    let pbv_s = if pct {
-                        let total_volume: f64 = pbv_s.sum().unwrap();
-                        if round >= 0 {
-                            (pbv_s / total_volume).round(round as u32).unwrap()
-                        } else {
-                            pbv_s / total_volume
-                        }
-                    } else if round >= 0 {
-                        pbv_s.round(round as u32).unwrap()
-                    } else {
-                        pbv_s
-                    };
-                    Some(pbv_s)
-                }
-            }).collect::<Vec<Option<Series>>>() 
+    let out = StructChunked::new(name, &f64_fields_from_rows(fields, rows))?;
+    Ok(out.into_series())
+}
+
+fn price_by_volume_distance_dtype(_input_fields: &[Field]) -> PolarsResult<Field> {
+    Ok(f64_struct_dtype("pbv_distance", &DISTANCE_FIELDS))
+}
+
+// Distance metrics between two volume distributions binned on the same grid:
+// earth mover's distance (in price), KL(current || previous), JS divergence
+// and the shift of the POC (in price).
+fn profile_distance(current: &[f64], previous: &[f64], interval: f64) -> Option<[f64; 4]> {
+    // keeps KL finite when a bin is empty in only one of the profiles
+    const EPS: f64 = 1e-10;
+    let total_current: f64 = current.iter().sum();
+    let total_previous: f64 = previous.iter().sum();
+    if total_current <= 0.0 || total_previous <= 0.0 {
+        return None;
+    }
+    let p: Vec<f64> = current.iter().map(|v| v / total_current).collect();
+    let q: Vec<f64> = previous.iter().map(|v| v / total_previous).collect();
+    let kl = |a: &[f64], b: &[f64]| -> f64 {
+        a.iter()
+            .zip(b.iter())
+            .filter(|(a, _)| **a > 0.0)
+            .map(|(a, b)| a * (a / b.max(EPS)).ln())
+            .sum()
+    };
+    let m: Vec<f64> = p.iter().zip(q.iter()).map(|(a, b)| (a + b) / 2.0).collect();
+    let mut cdf_diff = 0.0;
+    let mut wasserstein = 0.0;
+    for (a, b) in p.iter().zip(q.iter()) {
+        cdf_diff += a - b;
+        wasserstein += cdf_diff.abs() * interval;
+    }
+    let poc_shift = (poc_index(&p) as f64 - poc_index(&q) as f64) * interval;
+    Some([
+        wasserstein,
+        kl(&p, &q),
+        0.5 * kl(&p, &m) + 0.5 * kl(&q, &m),
+        poc_shift,
+    ])
+}
+
+#[polars_expr(output_type_func=price_by_volume_distance_dtype)]
+fn pbv_distance(inputs: &[Series], kwargs: PriceByVolumeDistanceKwargs) -> PolarsResult<Series> {
+    let price = f64_values(&inputs[0])?;
+    let volume = f64_values(&inputs[1])?;
+    let config = kwargs.profile.config(Normalize::None)?;
+    ensure_window(&config)?;
+    polars_ensure!(kwargs.k > 0, ComputeError: "`k` must be positive, got {}", kwargs.k);
+    let window_size = config.window_size;
+    let k = kwargs.k as usize;
+    // one grid over the `window_size + k` ticks both windows lie in, so the
+    // bins line up
+    let config = config.with_window_size(window_size + k);
+    let rows = metric_bins(&price, &[&volume], &config, kwargs.profile.parallel)?;
+    let distance: Vec<Option<[f64; 4]>> = on_pool(kwargs.profile.parallel, |parallel| {
+        map_rows(rows.len(), parallel, |i| {
+            let grid = rows[i].as_ref()?.grid;
+            let binned = |end: usize| {
+                let window_price = window_of(&price, end, window_size);
+                let mut volume_at_price =
+                    grid.sum(window_price, window_of(&volume, end, window_size));
+                smooth(&mut volume_at_price, config.smoothing, priced(window_price));
+                volume_at_price
+            };
+            profile_distance(&binned(i), &binned(i - k), grid.interval)
+        })
+    });
+
+    f64_struct_from_rows("pbv_distance", &DISTANCE_FIELDS, &distance)
+}
+
+const STATS_FIELDS: [&str; 6] = ["mean", "std", "skew", "kurtosis", "entropy", "bimodality"];
+
+fn price_by_volume_stats_dtype(_input_fields: &[Field]) -> PolarsResult<Field> {
+    Ok(f64_struct_dtype("pbv_stats", &STATS_FIELDS))
+}
+
+// Volume weighted mean, std, skewness and excess kurtosis of the window ticks,
+// Shannon entropy of the normalized bins and the bimodality coefficient.
+// The shape moments are NaN when every tick trades at one price.
+fn profile_stats(
+    window_price: &[f64],
+    window_volume: &[f64],
+    volume_at_price: &[f64],
+) -> Option<[f64; 6]> {
+    let ticks: Vec<(f64, f64)> = window_price
+        .iter()
+        .zip(window_volume)
+        .filter(|(p, v)| !p.is_nan() && !v.is_nan())
+        .map(|(p, v)| (*p, *v))
+        .collect();
+    let total_volume: f64 = ticks.iter().map(|(_, v)| v).sum();
+    if total_volume <= 0.0 {
+        return None;
+    }
+    let mean = ticks.iter().map(|(p, v)| p * v).sum::<f64>() / total_volume;
+    let moment = |k: i32| -> f64 {
+        ticks
+            .iter()
+            .map(|(p, v)| v * (p - mean).powi(k))
+            .sum::<f64>()
+            / total_volume
+    };
+    let m2 = moment(2);
+    let skew = moment(3) / m2.powf(1.5);
+    let kurtosis = moment(4) / (m2 * m2) - 3.0;
+    let binned_volume: f64 = volume_at_price.iter().sum();
+    let entropy = -volume_at_price
+        .iter()
+        .filter(|v| **v > 0.0)
+        .map(|v| {
+            let p = v / binned_volume;
+            p * p.ln()
+        })
+        .sum::<f64>();
+    let bimodality = (skew * skew + 1.0) / (kurtosis + 3.0);
+    Some([mean, m2.sqrt(), skew, kurtosis, entropy, bimodality])
+}
+
+#[polars_expr(output_type_func=price_by_volume_stats_dtype)]
+fn pbv_stats(inputs: &[Series], kwargs: PriceByVolumeStatsKwargs) -> PolarsResult<Series> {
+    let price = f64_values(&inputs[0])?;
+    let volume = f64_values(&inputs[1])?;
+    let config = kwargs.profile.config(Normalize::None)?;
+    ensure_window(&config)?;
+    let window_size = config.window_size;
+    let rows = metric_bins(&price, &[&volume], &config, kwargs.profile.parallel)?;
+    let stats: Vec<Option<[f64; 6]>> = on_pool(kwargs.profile.parallel, |parallel| {
+        map_rows(rows.len(), parallel, |i| {
+            let row = rows[i].as_ref()?;
+            profile_stats(
+                window_of(&price, i, window_size),
+                window_of(&volume, i, window_size),
+                &row.binned[0],
+            )
+        })
+    });
+
+    f64_struct_from_rows("pbv_stats", &STATS_FIELDS, &stats)
+}
+
+const SHAPE_FIELDS: [&str; 3] = ["poc_position", "mass_center", "valley_ratio"];
+
+fn price_by_volume_shape_dtype(_input_fields: &[Field]) -> PolarsResult<Field> {
+    let mut v = vec![Field::new("shape", DataType::String)];
+    v.extend(
+        SHAPE_FIELDS
+            .iter()
+            .map(|f| Field::new(f, DataType::Float64)),
+    );
+    Ok(Field::new("pbv_shape", DataType::Struct(v)))
+}
+
+// Scores used to classify a profile, positions are relative to the bins (0 is
+// the bottom of the range, 1 the top):
+// - poc_position: position of the POC bin center
+// - mass_center: volume weighted position of the bin centers
+// - valley_ratio: lowest bin between the two highest peaks over the smaller
+//   peak, 1 when the profile has a single peak
+fn profile_shape_scores(volume_at_price: &[f64]) -> Option<[f64; 3]> {
+    let bins = volume_at_price.len();
+    let total_volume: f64 = volume_at_price.iter().sum();
+    if bins == 0 || total_volume <= 0.0 {
+        return None;
+    }
+    let position = |n: usize| (n as f64 + 0.5) / bins as f64;
+    let poc = poc_index(volume_at_price);
+    let mass_center = volume_at_price
+        .iter()
+        .enumerate()
+        .map(|(n, v)| v * position(n))
+        .sum::<f64>()
+        / total_volume;
+
+    let mut peaks: Vec<usize> = (0..bins)
+        .filter(|&n| {
+            let v = volume_at_price[n];
+            (n == 0 || v > volume_at_price[n - 1]) && (n == bins - 1 || v >= volume_at_price[n + 1])
         })
         .collect();
+    peaks.sort_by(|a, b| volume_at_price[*b].total_cmp(&volume_at_price[*a]));
+    let valley_ratio = if peaks.len() < 2 {
+        1.0
+    } else {
+        let (first, second) = (peaks[0].min(peaks[1]), peaks[0].max(peaks[1]));
+        let valley = volume_at_price[first..=second]
+            .iter()
+            .fold(f64::INFINITY, |a, b| a.min(*b));
+        valley / volume_at_price[peaks[1]]
+    };
+    Some([position(poc), mass_center, valley_ratio])
+}
 
-    let label: Vec<Option<Series>> = (0..thread_count)
-        .into_par_iter()
-        .flat_map(|thread_count| {
-            let start_idx = thread_count * chunk_size + 1;
-            let end_idx = ((thread_count + 1) * chunk_size + 1).min(price_len + 1);
-            (start_idx..end_idx).map(|i| {
-                if i < window_size {
-                    None
-                } else {
-                    let start = (i - window_size) as i64;
-                    let window_price = price.slice(start, window_size);
-                    let max_price: f64 = window_price.max().unwrap().unwrap();
-                    let min_price: f64 = window_price.min().unwrap().unwrap();
-                    let range = max_price - min_price;
-                    let interval = range / bins as f64;
-    
-                    let price_label: Vec<f64> = (0..bins)
-                        .into_par_iter()
-                        .map(|n| {
-                            let lower_bound = min_price + n as f64 * interval;
-                            let upper_bound = min_price + (n + 1) as f64 * interval;
-                            if center_label {
-                                (lower_bound + upper_bound) / 2.0
-                            } else {
-                                lower_bound
-                            }
-                        })
-                        .collect();
-    
-                    let price_label_s = if round < 0 {
-                        Series::new("price", &price_label)
-                    } else {
-                        Series::new("price", &price_label)
-                            .round(round as u32)
-                            .unwrap()
-                    };
-                    Some(price_label_s)
-                }
-            }).collect::<Vec<Option<Series>>>()
+// B: two peaks split by a valley below `valley` of the smaller peak
+// P: POC in the top part of the range with the mass above `mass`
+// b: POC in the bottom part of the range with the mass below `1 - mass`
+// D: everything else
+fn classify_profile_shape(
+    scores: &[f64; 3],
+    upper: f64,
+    lower: f64,
+    valley: f64,
+    mass: f64,
+) -> &'static str {
+    let [poc_position, mass_center, valley_ratio] = *scores;
+    if valley_ratio < valley {
+        "B"
+    } else if poc_position >= upper && mass_center > mass {
+        "P"
+    } else if poc_position <= lower && mass_center < 1.0 - mass {
+        "b"
+    } else {
+        "D"
+    }
+}
+
+#[polars_expr(output_type_func=price_by_volume_shape_dtype)]
+fn pbv_shape(inputs: &[Series], kwargs: PriceByVolumeShapeKwargs) -> PolarsResult<Series> {
+    let price = f64_values(&inputs[0])?;
+    let volume = f64_values(&inputs[1])?;
+    let config = kwargs.profile.config(Normalize::None)?;
+    ensure_window(&config)?;
+    polars_ensure!(
+        (0.0..=1.0).contains(&kwargs.mass),
+        ComputeError: "`mass` must be within [0, 1], got {}", kwargs.mass
+    );
+    let rows = metric_bins(&price, &[&volume], &config, kwargs.profile.parallel)?;
+    let scores: Vec<Option<[f64; 3]>> = rows
+        .iter()
+        .map(|row| {
+            let row = row.as_ref()?;
+            if row.grid.max <= row.grid.min {
+                // no range, no shape
+                return None;
+            }
+            profile_shape_scores(&row.binned[0])
         })
         .collect();
 
-    let label_series = Series::new("price", &label);
-    let pbv_series = Series::new("volume", &pbv);
-    let out = StructChunked::new("pbv", &[label_series, pbv_series])?;
+    let shape: StringChunked = scores
+        .iter()
+        .map(|row| {
+            row.as_ref().map(|scores| {
+                classify_profile_shape(
+                    scores,
+                    kwargs.upper,
+                    kwargs.lower,
+                    kwargs.valley,
+                    kwargs.mass,
+                )
+            })
+        })
+        .collect();
+    let mut fields = vec![shape.with_name("shape").into_series()];
+    fields.extend(f64_fields_from_rows(&SHAPE_FIELDS, &scores));
+    let out = StructChunked::new("pbv_shape", &fields)?;
     Ok(out.into_series())
 }
 
-#[polars_expr(output_type_func=price_by_volume_dtype)]
-fn pbv_not_par(inputs: &[Series], kwargs: PriceByVolumeKwargs) -> PolarsResult<Series> {
-    let price = &inputs[0].to_float()?;
-    let volume = &inputs[1].to_float()?;
-    price_by_volume(
-        price,
-        volume,
-        kwargs.window_size,
-        kwargs.bins,
-        kwargs.center_label,
-        kwargs.round,
-        false,
-    )
+fn price_by_volume_position_dtype(_input_fields: &[Field]) -> PolarsResult<Field> {
+    let v: Vec<Field> = vec![
+        Field::new("percentile", DataType::Float64),
+        Field::new("poc_distance_ticks", DataType::Float64),
+        Field::new("poc_distance_bins", DataType::Float64),
+        Field::new("in_value_area", DataType::Boolean),
+    ];
+    Ok(Field::new("pbv_position", DataType::Struct(v)))
 }
 
-#[polars_expr(output_type_func=price_by_volume_dtype)]
-fn pbv(inputs: &[Series], kwargs: PriceByVolumeKwargs) -> PolarsResult<Series> {
-    let price = &inputs[0].to_float()?;
-    let volume = &inputs[1].to_float()?;
-    price_by_volume_par(
-        price,
-        volume,
-        kwargs.window_size,
-        kwargs.bins,
-        kwargs.center_label,
-        kwargs.round,
-        false,
-    )
+struct ProfilePosition {
+    percentile: f64,
+    poc_distance: f64,
+    poc_distance_bins: f64,
+    in_value_area: bool,
 }
 
-#[polars_expr(output_type_func=price_by_volume_dtype)]
-fn pbv_pct(inputs: &[Series], kwargs: PriceByVolumeKwargs) -> PolarsResult<Series> {
-    let price = &inputs[0].to_float()?;
-    let volume = &inputs[1].to_float()?;
-    price_by_volume_par(
+// Where the last price of the window sits inside the window profile.
+fn profile_position(
+    window_price: &[f64],
+    window_volume: &[f64],
+    volume_at_price: &[f64],
+    grid: &Grid,
+    value_area: f64,
+) -> Option<ProfilePosition> {
+    let last_price = *window_price.last()?;
+    if last_price.is_nan() {
+        return None;
+    }
+    let mut total_volume = 0.0;
+    let mut volume_below = 0.0;
+    for (p, v) in window_price.iter().zip(window_volume) {
+        if !p.is_nan() && !v.is_nan() {
+            total_volume += v;
+            if *p <= last_price {
+                volume_below += v;
+            }
+        }
+    }
+    if total_volume <= 0.0 {
+        return None;
+    }
+    let interval = grid.interval;
+    let poc = poc_index(volume_at_price);
+    let poc_price = (grid.lower(poc) + grid.upper(poc)) / 2.0;
+    let (low, high) = value_area_index(volume_at_price, value_area);
+    let value_area_low = grid.lower(low);
+    let value_area_high = grid.upper(high);
+    Some(ProfilePosition {
+        percentile: volume_below / total_volume,
+        poc_distance: last_price - poc_price,
+        poc_distance_bins: if interval > 0.0 {
+            (last_price - poc_price) / interval
+        } else {
+            0.0
+        },
+        in_value_area: value_area_low <= last_price && last_price <= value_area_high,
+    })
+}
+
+#[polars_expr(output_type_func=price_by_volume_position_dtype)]
+fn pbv_position(inputs: &[Series], kwargs: PriceByVolumePositionKwargs) -> PolarsResult<Series> {
+    let price = f64_values(&inputs[0])?;
+    let volume = f64_values(&inputs[1])?;
+    let config = kwargs.profile.config(Normalize::None)?;
+    ensure_window(&config)?;
+    polars_ensure!(
+        (0.0..=1.0).contains(&kwargs.value_area),
+        ComputeError: "`value_area` must be within [0, 1], got {}", kwargs.value_area
+    );
+    let window_size = config.window_size;
+    let rows = metric_bins(&price, &[&volume], &config, kwargs.profile.parallel)?;
+    let position: Vec<Option<ProfilePosition>> = on_pool(kwargs.profile.parallel, |parallel| {
+        map_rows(rows.len(), parallel, |i| {
+            let row = rows[i].as_ref()?;
+            profile_position(
+                window_of(&price, i, window_size),
+                window_of(&volume, i, window_size),
+                &row.binned[0],
+                &row.grid,
+                kwargs.value_area,
+            )
+        })
+    });
+
+    let percentile: Float64Chunked = position
+        .iter()
+        .map(|p| p.as_ref().map(|p| p.percentile))
+        .collect();
+    let poc_distance_ticks: Float64Chunked = position
+        .iter()
+        .map(|p| {
+            let tick_size = kwargs.tick_size.or(match config.binning {
+                Binning::TickSize(tick_size) => Some(tick_size),
+                Binning::Bins(_) => None,
+            })?;
+            p.as_ref().map(|p| p.poc_distance / tick_size)
+        })
+        .collect();
+    let poc_distance_bins: Float64Chunked = position
+        .iter()
+        .map(|p| p.as_ref().map(|p| p.poc_distance_bins))
+        .collect();
+    let in_value_area: BooleanChunked = position
+        .iter()
+        .map(|p| p.as_ref().map(|p| p.in_value_area))
+        .collect();
+    let out = StructChunked::new(
+        "pbv_position",
+        &[
+            percentile.with_name("percentile").into_series(),
+            poc_distance_ticks
+                .with_name("poc_distance_ticks")
+                .into_series(),
+            poc_distance_bins
+                .with_name("poc_distance_bins")
+                .into_series(),
+            in_value_area.with_name("in_value_area").into_series(),
+        ],
+    )?;
+    Ok(out.into_series())
+}
+
+fn price_by_volume_naked_poc_dtype(input_fields: &[Field]) -> PolarsResult<Field> {
+    let v: Vec<Field> = vec![
+        Field::new("poc", DataType::List(Box::new(DataType::Float64))),
+        Field::new(
+            "session",
+            DataType::List(Box::new(input_fields[2].dtype.clone())),
+        ),
+        Field::new("age", DataType::List(Box::new(DataType::UInt32))),
+    ];
+    Ok(Field::new("pbv_naked_poc", DataType::Struct(v)))
+}
+
+fn naked_poc(
+    price: &Series,
+    volume: &Series,
+    session: &Series,
+    config: &ProfileConfig,
+    parallel: Parallel,
+) -> PolarsResult<Series> {
+    let offsets = group_offsets(session)?;
+    // (first row, length) of every session
+    let mut sessions: Vec<(usize, usize)> = vec![];
+    for (i, offset) in offsets.iter().enumerate() {
+        if *offset == i {
+            sessions.push((i, 1));
+        } else if let Some(last) = sessions.last_mut() {
+            last.1 += 1;
+        }
+    }
+    let price_values = f64_values(price)?;
+    let volume_values = f64_values(volume)?;
+    let pocs = on_pool(parallel, |parallel| {
+        map_rows(sessions.len(), parallel, |idx| {
+            let (start, len) = sessions[idx];
+            let session = start..start + len;
+            let profile = VolumeProfile::from_window(
+                &price_values[session.clone()],
+                &volume_values[session],
+                config,
+            )?;
+            Ok(profile.and_then(|profile| profile.poc()))
+        })
+    })
+    .into_iter()
+    .collect::<Result<Vec<Option<f64>>, ConfigError>>()?;
+
+    // walk the rows once, a prior session poc stays naked until the price
+    // moves onto or through it
+    let mut naked: Vec<(f64, usize)> = vec![];
+    let mut last_price: Option<f64> = None;
+    let mut session_idx = 0;
+    let mut poc_list = vec![];
+    let mut session_list = vec![];
+    let mut age_list = vec![];
+    for (i, p) in price.f64()?.into_iter().enumerate() {
+        if i > 0 && offsets[i] == i {
+            if let Some(poc) = pocs[session_idx] {
+                naked.push((poc, session_idx));
+            }
+            session_idx += 1;
+        }
+        if let Some(p) = p {
+            if let Some(last_price) = last_price {
+                naked.retain(|(poc, _)| (last_price - poc) * (p - poc) > 0.0);
+            }
+            last_price = Some(p);
+        }
+        let poc: Vec<f64> = naked.iter().map(|(poc, _)| *poc).collect();
+        let first_rows: Vec<IdxSize> = naked
+            .iter()
+            .map(|(_, idx)| sessions[*idx].0 as IdxSize)
+            .collect();
+        let age: Vec<u32> = naked
+            .iter()
+            .map(|(_, idx)| (session_idx - idx) as u32)
+            .collect();
+        poc_list.push(Series::new("poc", &poc));
+        session_list.push(session.take_slice(&first_rows)?);
+        age_list.push(Series::new("age", &age));
+    }
+    let out = StructChunked::new(
+        "pbv_naked_poc",
+        &[
+            Series::new("poc", &poc_list),
+            Series::new("session", &session_list),
+            Series::new("age", &age_list),
+        ],
+    )?;
+    Ok(out.into_series())
+}
+
+#[polars_expr(output_type_func=price_by_volume_naked_poc_dtype)]
+fn pbv_naked_poc(inputs: &[Series], kwargs: PriceByVolumeNakedPocKwargs) -> PolarsResult<Series> {
+    let price = &inputs[0].cast(&DataType::Float64)?;
+    let volume = &inputs[1].cast(&DataType::Float64)?;
+    let config = kwargs.profile.config(Normalize::None)?;
+    naked_poc(price, volume, &inputs[2], &config, kwargs.profile.parallel)
+}
+
+const DEVELOPING_FIELDS: [&str; 3] = ["poc", "vah", "val"];
+
+fn price_by_volume_developing_dtype(_input_fields: &[Field]) -> PolarsResult<Field> {
+    Ok(f64_struct_dtype("pbv_developing", &DEVELOPING_FIELDS))
+}
+
+// Volume histogram on a fixed tick grid that grows with the price range, so
+// every tick is added in O(1) without re-binning the history. The POC and the
+// total volume are kept up to date as well, so the levels only read the bins
+// of the value area.
+struct DevelopingProfile {
+    tick_size: f64,
+    closed: Closed,
+    // tick number of the first bin, see `Closed::tick_number`
+    base: i64,
+    volume_at_price: VecDeque<f64>,
+    // bin of the POC, counted from `base`
+    poc: usize,
+    total: f64,
+}
+
+impl DevelopingProfile {
+    fn new(tick_size: f64, closed: Closed) -> Self {
+        Self {
+            tick_size,
+            closed,
+            base: 0,
+            volume_at_price: VecDeque::new(),
+            poc: 0,
+            total: 0.0,
+        }
+    }
+
+    // Ticks with a NaN or infinite price or a NaN volume are skipped, the
+    // result tells whether the tick was added. The grid spans every price so
+    // far and fails once it needs more than `MAX_BINS` ticks, like the tick
+    // size grids of the rolling profiles, or when a price is too many ticks
+    // from zero to keep its tick number exact.
+    fn push(&mut self, price: f64, volume: f64) -> PolarsResult<bool> {
+        if !price.is_finite() || volume.is_nan() {
+            return Ok(false);
+        }
+        let tick = self.closed.tick_number(price, self.tick_size);
+        // past 2^53 the tick numbers are no longer exact
+        polars_ensure!(
+            tick.abs() <= (1u64 << 53) as f64,
+            ComputeError: "price {} is too far from zero for tick_size {}", price, self.tick_size
+        );
+        let idx = tick as i64;
+        if self.volume_at_price.is_empty() {
+            self.base = idx;
+        }
+        let top = self.base + self.volume_at_price.len().max(1) as i64 - 1;
+        let span = idx.max(top) - idx.min(self.base) + 1;
+        polars_ensure!(
+            span <= MAX_BINS as i64,
+            ComputeError: "tick_size {} gives {} ticks between the prices of the group, more than {}",
+            self.tick_size, span, MAX_BINS
+        );
+        while idx < self.base {
+            self.volume_at_price.push_front(0.0);
+            self.base -= 1;
+            self.poc += 1;
+        }
+        let pos = (idx - self.base) as usize;
+        if pos >= self.volume_at_price.len() {
+            self.volume_at_price.resize(pos + 1, 0.0);
+        }
+        self.volume_at_price[pos] += volume;
+        self.total += volume;
+        // the lowest bin wins a tie, as in `poc_index`. Only negative volume
+        // can move the POC away from its bin, and new empty bins can only beat
+        // a POC without positive volume; then the POC is searched again.
+        let (added, poc) = (self.volume_at_price[pos], self.volume_at_price[self.poc]);
+        if (pos == self.poc && volume < 0.0) || poc <= 0.0 {
+            self.poc = poc_index(self.volume_at_price.make_contiguous());
+        } else if added > poc || (added == poc && pos < self.poc) {
+            self.poc = pos;
+        }
+        Ok(true)
+    }
+
+    // Tick numbers of the POC, value area high and value area low.
+    fn levels(&self, value_area: f64) -> Option<[i64; 3]> {
+        if self.volume_at_price.is_empty() {
+            return None;
+        }
+        let (low, high) = value_area_around(
+            |n| self.volume_at_price[n],
+            self.volume_at_price.len(),
+            self.poc,
+            self.total,
+            value_area,
+        );
+        Some([self.poc, high, low].map(|n| self.base + n as i64))
+    }
+}
+
+// The developing levels on the tick grid of `config`, priced by its label and
+// rounding.
+fn developing_levels(
+    price: &Series,
+    volume: &Series,
+    config: &ProfileConfig,
+    value_area: f64,
+    group_offsets: Option<&[usize]>,
+) -> PolarsResult<Series> {
+    let Binning::TickSize(tick_size) = config.binning else {
+        polars_bail!(ComputeError: "pass `tick_size` or a config with a `tick_size`")
+    };
+    polars_ensure!(
+        (0.0..=1.0).contains(&value_area),
+        ComputeError: "`value_area` must be within [0, 1], got {}", value_area
+    );
+    let offset = match config.label {
+        Label::Lower => 0.0,
+        Label::Center => 0.5,
+        Label::Upper => 1.0,
+        label => polars_bail!(
+            ComputeError: "`pbv_developing` takes `lower`, `center` or `upper` labels, got {:?}", label
+        ),
+    };
+    let price_of = |tick: i64| round_to(tick as f64 * tick_size + offset * tick_size, config.round);
+    let mut profile = DevelopingProfile::new(tick_size, config.closed);
+    let mut levels: Vec<Option<[f64; 3]>> = Vec::with_capacity(price.len());
+    let mut last = None;
+    for (i, (p, v)) in price.f64()?.into_iter().zip(volume.f64()?).enumerate() {
+        // a new group starts a new profile
+        if i > 0 && group_offsets.is_some_and(|offsets| offsets[i] == i) {
+            profile = DevelopingProfile::new(tick_size, config.closed);
+            last = None;
+        }
+        // rows that add nothing keep the levels of the row before
+        if let (Some(p), Some(v)) = (p, v) {
+            if profile.push(p, v)? {
+                last = profile.levels(value_area).map(|ticks| ticks.map(price_of));
+            }
+        }
+        levels.push(last);
+    }
+    f64_struct_from_rows("pbv_developing", &DEVELOPING_FIELDS, &levels)
+}
+
+#[polars_expr(output_type_func=price_by_volume_developing_dtype)]
+fn pbv_developing(
+    inputs: &[Series],
+    kwargs: PriceByVolumeDevelopingKwargs,
+) -> PolarsResult<Series> {
+    let price = &inputs[0].cast(&DataType::Float64)?;
+    let volume = &inputs[1].cast(&DataType::Float64)?;
+    let offsets = inputs.get(2).map(group_offsets).transpose()?;
+    // the profile grows over the whole group, the window of a config does not
+    // apply. Without a config the levels are the ticks themselves.
+    let mut config = match kwargs.profile.passed_config()? {
+        Some(config) => config,
+        None => ProfileConfig::new(0, 1).with_label(Label::Lower),
+    };
+    if let Some(tick_size) = kwargs.tick_size {
+        config = config.with_binning(Binning::TickSize(tick_size));
+        config.validate()?;
+    }
+    developing_levels(
         price,
         volume,
-        kwargs.window_size,
-        kwargs.bins,
-        kwargs.center_label,
-        kwargs.round,
-        true,
+        &config,
+        kwargs.value_area,
+        offsets.as_deref(),
     )
 }
 
+fn price_by_volume_excess_dtype(_input_fields: &[Field]) -> PolarsResult<Field> {
+    let list_f64 = DataType::List(Box::new(DataType::Float64));
+    let v: Vec<Field> = vec![
+        Field::new("single_print_lower", list_f64.clone()),
+        Field::new("single_print_upper", list_f64),
+        Field::new("poor_high_lower", DataType::Float64),
+        Field::new("poor_high_upper", DataType::Float64),
+        Field::new("poor_low_lower", DataType::Float64),
+        Field::new("poor_low_upper", DataType::Float64),
+    ];
+    Ok(Field::new("pbv_excess", DataType::Struct(v)))
+}
+
+struct ProfileExcess {
+    single_print: Vec<(f64, f64)>,
+    poor_high: Option<(f64, f64)>,
+    poor_low: Option<(f64, f64)>,
+}
+
+// Single prints are runs of bins hit by exactly one row. The extreme bins are
+// poor when their volume is at least `poor_ratio` times the average volume of
+// the non-empty bins, i.e. the auction ended without excess.
+fn profile_excess(
+    volume_at_price: &[f64],
+    count_at_price: &[f64],
+    grid: &Grid,
+    poor_ratio: f64,
+) -> ProfileExcess {
+    let bins = volume_at_price.len();
+    let bound = |n: usize| grid.lower(n);
+    let mut single_print: Vec<(f64, f64)> = vec![];
+    let mut last_single = None;
+    for (n, count) in count_at_price.iter().enumerate() {
+        if *count == 1.0 {
+            match single_print.last_mut() {
+                Some(range) if last_single == Some(n - 1) => range.1 = bound(n + 1),
+                _ => single_print.push((bound(n), bound(n + 1))),
+            }
+            last_single = Some(n);
+        }
+    }
+    let traded: Vec<f64> = volume_at_price
+        .iter()
+        .zip(count_at_price)
+        .filter(|(_, c)| **c > 0.0)
+        .map(|(v, _)| *v)
+        .collect();
+    let average = traded.iter().sum::<f64>() / traded.len().max(1) as f64;
+    let is_poor = |v: f64| bins > 1 && average > 0.0 && v >= poor_ratio * average;
+    ProfileExcess {
+        single_print,
+        poor_high: is_poor(volume_at_price[bins - 1]).then(|| (bound(bins - 1), bound(bins))),
+        poor_low: is_poor(volume_at_price[0]).then(|| (bound(0), bound(1))),
+    }
+}
+
+#[polars_expr(output_type_func=price_by_volume_excess_dtype)]
+fn pbv_excess(inputs: &[Series], kwargs: PriceByVolumeExcessKwargs) -> PolarsResult<Series> {
+    let price = f64_values(&inputs[0])?;
+    let volume = f64_values(&inputs[1])?;
+    // the trade count is the volume of a window where every row weighs one
+    let ones = vec![1.0; price.len()];
+    let config = kwargs.profile.config(Normalize::None)?;
+    ensure_window(&config)?;
+    polars_ensure!(
+        kwargs.poor_ratio > 0.0,
+        ComputeError: "`poor_ratio` must be positive, got {}", kwargs.poor_ratio
+    );
+    // counts are not smoothed, a single print stays a single print
+    let config = config.with_smoothing(Smoothing::None);
+    let rows = metric_bins(&price, &[&volume, &ones], &config, kwargs.profile.parallel)?;
+    let excess: Vec<Option<ProfileExcess>> = rows
+        .iter()
+        .map(|row| {
+            let row = row.as_ref()?;
+            Some(profile_excess(
+                &row.binned[0],
+                &row.binned[1],
+                &row.grid,
+                kwargs.poor_ratio,
+            ))
+        })
+        .collect();
+
+    let single_print_lower: Vec<Option<Series>> = excess
+        .iter()
+        .map(|e| {
+            e.as_ref().map(|e| {
+                let lower: Vec<f64> = e.single_print.iter().map(|r| r.0).collect();
+                Series::new("single_print_lower", lower)
+            })
+        })
+        .collect();
+    let single_print_upper: Vec<Option<Series>> = excess
+        .iter()
+        .map(|e| {
+            e.as_ref().map(|e| {
+                let upper: Vec<f64> = e.single_print.iter().map(|r| r.1).collect();
+                Series::new("single_print_upper", upper)
+            })
+        })
+        .collect();
+    let range_field = |name: &str, f: fn(&ProfileExcess) -> Option<f64>| -> Series {
+        let values: Float64Chunked = excess.iter().map(|e| e.as_ref().and_then(f)).collect();
+        values.with_name(name).into_series()
+    };
+    let out = StructChunked::new(
+        "pbv_excess",
+        &[
+            Series::new("single_print_lower", &single_print_lower),
+            Series::new("single_print_upper", &single_print_upper),
+            range_field("poor_high_lower", |e| e.poor_high.map(|r| r.0)),
+            range_field("poor_high_upper", |e| e.poor_high.map(|r| r.1)),
+            range_field("poor_low_lower", |e| e.poor_low.map(|r| r.0)),
+            range_field("poor_low_upper", |e| e.poor_low.map(|r| r.1)),
+        ],
+    )?;
+    Ok(out.into_series())
+}
+
 fn price_by_volume_topn_volume_price_dtype(input_fields: &[Field]) -> PolarsResult<Field> {
     let field = Field::new(
         "pbv_topn_vp",
@@ -294,81 +1968,61 @@ fn price_by_volume_topn_volume_price_dtype(input_fields: &[Field]) -> PolarsResu
     Ok(field)
 }
 
+// labels, volume and the bins ordered by volume
+type TopNRow = (Vec<f64>, Vec<f64>, Vec<usize>);
+
+// Labels and volume of every window, with the bins ordered by volume, the
+// lowest bin first on ties. The volume is normalized and rounded after the
+// ordering so rounding cannot reorder close bins.
+fn price_by_volume_topn(
+    inputs: &[Series],
+    kwargs: &PriceByVolumeTopNKwargs,
+) -> PolarsResult<Vec<Option<TopNRow>>> {
+    let price = f64_values(&inputs[0])?;
+    let volume = f64_values(&inputs[1])?;
+    let config = kwargs.profile.config(Normalize::from_pct(kwargs.pct, false))?;
+    let mut raw = config.clone();
+    raw.round = None;
+    raw.normalize = Normalize::None;
+    let rows = on_pool(kwargs.profile.parallel, |parallel| {
+        rolling_bins(&price, &[&volume], None, None, &raw, parallel)
+    })?;
+    Ok(rows
+        .into_iter()
+        .map(|row| {
+            row.map(|mut row| {
+                let mut volume_at_price = row.binned.swap_remove(0);
+                let mut order: Vec<usize> = (0..volume_at_price.len()).collect();
+                order.sort_by(|&a, &b| volume_at_price[b].total_cmp(&volume_at_price[a]));
+                order.truncate(kwargs.n);
+                let label = row
+                    .labels
+                    .into_iter()
+                    .map(|p| round_to(p, config.round))
+                    .collect();
+                normalize(
+                    &mut volume_at_price,
+                    config.normalize,
+                    row.grid.interval,
+                    config.round,
+                );
+                (label, volume_at_price, order)
+            })
+        })
+        .collect())
+}
+
 #[polars_expr(output_type_func=price_by_volume_topn_volume_price_dtype)]
 fn pbv_topn_vp(inputs: &[Series], kwargs: PriceByVolumeTopNKwargs) -> PolarsResult<Series> {
-    let price = &inputs[0].to_float()?;
-    let volume = &inputs[1].to_float()?;
-    let window_size = kwargs.window_size as usize;
-    let thread_count = rayon::current_num_threads() * 64; // for small chunk size;
-    let chunk_size = (price.len() + thread_count - 1) / thread_count;
-
-    let pbv_topn: Vec<Option<Series>> = (0..thread_count)
-        .into_par_iter()
-        .flat_map(|thread_idx| {
-            let start_idx = thread_idx * chunk_size + 1;
-            let end_idx = ((thread_idx + 1) * chunk_size + 1).min(price.len() + 1);
-            (start_idx..end_idx).map(|i| {  
-                if i < window_size {
-                    None
-                } else {
-                    let mut volume_at_price = vec![];
-                    let mut price_label = vec![];
-                    let start = (i - window_size) as i64;
-                    let window_price = price.slice(start, window_size);
-                    let window_volume = volume.slice(start, window_size);
-                    let max_price: f64 = window_price.max().unwrap().unwrap();
-                    let min_price: f64 = window_price.min().unwrap().unwrap();
-                    let range = max_price - min_price;
-                    let interval = range / kwargs.bins as f64;
-    
-                    for n in 0..kwargs.bins {
-                        let lower_bound = min_price + n as f64 * interval;
-                        let upper_bound = min_price + (n + 1) as f64 * interval;
-                        let center = (lower_bound + upper_bound) / 2.0;
-                        if n == kwargs.bins - 1 {
-                            let v: f64 = window_volume
-                                .filter(&window_price.gt_eq(lower_bound).unwrap())
-                                .unwrap()
-                                .sum()
-                                .unwrap();
-                            volume_at_price.push(v);
-                        } else {
-                            let mask = window_price.gt_eq(lower_bound).unwrap()
-                                & window_price.lt(upper_bound).unwrap();
-                            let v = window_volume.filter(&mask).unwrap().sum().unwrap();
-                            volume_at_price.push(v);
-                        }
-                        let label = if kwargs.center_label {
-                            center
-                        } else {
-                            lower_bound
-                        };
-                        price_label.push(label);
-                    }
-    
-                    let price_label_s = Series::new("price", &price_label);
-                    let price_label_s_round = price_label_s.round(kwargs.round as u32).unwrap();
-                    let price_label_s = if kwargs.round < 0 {
-                        price_label_s.f64().unwrap()
-                    } else {
-                        price_label_s_round.f64().unwrap()
-                    };
-                    let pbv_s = Series::new("volume", &volume_at_price);
-                    let pbv_s_idx_sort =
-                        pbv_s.arg_sort(SortOptions::default().with_order_descending(true));
-    
-                    let pbv_topn_s = pbv_s_idx_sort
-                        .slice(0, kwargs.n)
-                        .iter()
-                        .map(|opt_idx| opt_idx.map(|idx| price_label_s.get(idx as usize).unwrap()))
-                        .collect::<Vec<Option<f64>>>();
-    
-                    Some(Series::new("pbv_topn", &pbv_topn_s))
-                }
-            }).collect::<Vec<Option<Series>>>()
+    let pbv_topn: Vec<Option<Series>> = price_by_volume_topn(inputs, &kwargs)?
+        .into_iter()
+        .map(|row| {
+            row.map(|(label, _, order)| {
+                let top: Vec<f64> = order.iter().map(|&n| label[n]).collect();
+                Series::new("pbv_topn", &top)
+            })
         })
         .collect();
-
     Ok(Series::new("pbv_topn_vp", pbv_topn))
 }
 
@@ -382,87 +2036,64 @@ fn price_by_volume_topn_volume_dtype(_input_fields: &[Field]) -> PolarsResult<Fi
 
 #[polars_expr(output_type_func=price_by_volume_topn_volume_dtype)]
 fn pbv_topn_v(inputs: &[Series], kwargs: PriceByVolumeTopNKwargs) -> PolarsResult<Series> {
-    let price = &inputs[0].to_float()?;
-    let volume = &inputs[1].to_float()?;
-    let window_size = kwargs.window_size as usize;
-    let thread_count = rayon::current_num_threads() * 64; // for small chunk size;
-    let chunk_size = (price.len() + thread_count - 1) / thread_count;
-
-    let pbv_topn: Vec<Option<Series>> = (0..thread_count)
-        .into_par_iter()
-        .flat_map(|thread_idx| {
-            let start_idx = thread_idx * chunk_size + 1;
-            let end_idx = ((thread_idx + 1) * chunk_size + 1).min(price.len() + 1);
-            (start_idx..end_idx).map(|i| {
-                if i < window_size {
-                    None
-                } else {
-                    let mut volume_at_price = vec![];
-                    let mut price_label = vec![];
-                    let start = (i - window_size) as i64;
-                    let window_price = price.slice(start, window_size);
-                    let window_volume = volume.slice(start, window_size);
-                    let max_price: f64 = window_price.max().unwrap().unwrap();
-                    let min_price: f64 = window_price.min().unwrap().unwrap();
-                    let range = max_price - min_price;
-                    let interval = range / kwargs.bins as f64;
-
-                    for n in 0..kwargs.bins {
-                        let lower_bound = min_price + n as f64 * interval;
-                        let upper_bound = min_price + (n + 1) as f64 * interval;
-                        let center = (lower_bound + upper_bound) / 2.0;
-                        if n == kwargs.bins - 1 {
-                            let v: f64 = window_volume
-                                .filter(&window_price.gt_eq(lower_bound).unwrap()).unwrap()
-                                .sum().unwrap();
-                            volume_at_price.push(v);
-                        } else {
-                            let mask = window_price.gt_eq(lower_bound).unwrap() & window_price.lt(upper_bound).unwrap();
-                            let v = window_volume.filter(&mask).unwrap().sum().unwrap();
-                            volume_at_price.push(v);
-                        }
-                        let label = if kwargs.center_label {
-                            center
-                        } else {
-                            lower_bound
-                        };
-                        price_label.push(label);
-                    }
-
-                    let pbv_s = Series::new("volume", &volume_at_price);
-                    let total_v: f64 = pbv_s.sum().unwrap();
-                    let mut pbv_s_pct;
-                    let pbv_s_round;
-                    let pbv_s_f64 = if kwargs.pct {
-                        pbv_s_pct = pbv_s.clone() / total_v;
-                        pbv_s_pct = if kwargs.round < 0 {
-                            pbv_s_pct
-                        } else {
-                            pbv_s_pct.round(kwargs.round as u32).unwrap()
-                        };
-                        pbv_s_pct.f64().unwrap()
-                    } else {
-                        pbv_s_round = if kwargs.round < 0 {
-                            pbv_s.clone()
-                        } else {
-                            pbv_s.round(kwargs.round as u32).unwrap()
-                        };
-                        pbv_s_round.f64().unwrap()
-                    };
-
-                    let pbv_s_idx_sort = pbv_s.arg_sort(SortOptions::default().with_order_descending(true));
-
-                    let pbv_topn_s = pbv_s_idx_sort
-                        .slice(0, kwargs.n)
-                        .iter()
-                        .map(|opt_idx| opt_idx.map(|idx| pbv_s_f64.get(idx as usize).unwrap()))
-                        .collect::<Vec<Option<f64>>>();
-
-                    Some(Series::new("pbv_topn", &pbv_topn_s))
-                }
-            }).collect::<Vec<Option<Series>>>()
+    let pbv_topn: Vec<Option<Series>> = price_by_volume_topn(inputs, &kwargs)?
+        .into_iter()
+        .map(|row| {
+            row.map(|(_, volume_at_price, order)| {
+                let top: Vec<f64> = order.iter().map(|&n| volume_at_price[n]).collect();
+                Series::new("pbv_topn", &top)
+            })
         })
         .collect();
-
     Ok(Series::new("pbv_topn_v", pbv_topn))
 }
+
+fn price_by_volume_quantile_dtype(_input_fields: &[Field]) -> PolarsResult<Field> {
+    let field = Field::new(
+        "pbv_quantile",
+        DataType::List(Box::new(Float64Type::get_dtype())),
+    );
+    Ok(field)
+}
+
+// Volume-weighted price quantiles of every window, one price per requested
+// quantile, taken from the raw ticks rather than the bins. With `by` the
+// window does not reach into the previous group.
+#[polars_expr(output_type_func=price_by_volume_quantile_dtype)]
+fn pbv_quantile(inputs: &[Series], kwargs: PriceByVolumeQuantileKwargs) -> PolarsResult<Series> {
+    polars_ensure!(
+        kwargs.quantiles.iter().all(|q| (0.0..=1.0).contains(q)),
+        ComputeError: "`quantiles` must be within [0, 1], got {:?}", kwargs.quantiles
+    );
+    let price = f64_values(&inputs[0])?;
+    let volume = f64_values(&inputs[1])?;
+    let offsets = if kwargs.has_by {
+        Some(group_offsets(&inputs[2])?)
+    } else {
+        None
+    };
+    // only the window of a config applies, the ticks are not binned
+    let window_size = match kwargs.profile.passed_config()? {
+        Some(config) => config.window_size,
+        None => kwargs.profile.fields.window_size.unwrap_or(0).max(0) as usize,
+    };
+    let quantiles: Vec<Option<Series>> = on_pool(kwargs.profile.parallel, |parallel| {
+        map_rows(price.len(), parallel, |i| {
+            if window_size == 0 {
+                return None;
+            }
+            let start = (i + 1).checked_sub(window_size)?;
+            if offsets.as_ref().is_some_and(|offsets| offsets[i] > start) {
+                return None;
+            }
+            let q = volume_quantiles(
+                &price[start..=i],
+                &volume[start..=i],
+                &kwargs.quantiles,
+                kwargs.interpolation,
+            )?;
+            Some(Series::new("pbv_quantile", &q))
+        })
+    });
+    Ok(Series::new("pbv_quantile", quantiles))
+}
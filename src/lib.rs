@@ -1,16 +1,23 @@
+pub mod profile;
+
+#[cfg(feature = "python")]
 mod expressions;
+#[cfg(feature = "python")]
 mod utils;
 
-#[cfg(target_os = "linux")]
+#[cfg(all(feature = "python", target_os = "linux"))]
 use jemallocator::Jemalloc;
 
 #[global_allocator]
-#[cfg(target_os = "linux")]
+#[cfg(all(feature = "python", target_os = "linux"))]
 static ALLOC: Jemalloc = Jemalloc;
 
+#[cfg(feature = "python")]
 use pyo3::types::PyModule;
+#[cfg(feature = "python")]
 use pyo3::{pymodule, Bound, PyResult, Python};
 
+#[cfg(feature = "python")]
 #[pymodule]
 fn polars_pbv(_py: Python, m: &Bound<'_, PyModule>) -> PyResult<()> {
     m.add("__version__", env!("CARGO_PKG_VERSION"))?;
@@ -0,0 +1,1713 @@
+// Pure Rust volume profile kernel. Nothing in here depends on polars or pyo3,
+// the plugin expressions are thin wrappers around it, so native code gets the
+// exact same numbers as `pbv` in polars.
+//
+//     use polars_pbv::profile::{rolling_profile, ProfileConfig};
+//
+//     let config = ProfileConfig::new(3, 2);
+//     let profiles = rolling_profile(&price, &volume, &config)?;
+//
+// Missing prices can be passed as NaN, they are skipped like nulls in polars.
+use rayon::prelude::*;
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Deserializer, Serialize};
+
+// How the binned volume (or any weight) is scaled before it is returned.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Normalize {
+    #[default]
+    None,
+    // share of the window total
+    Sum,
+    // share of the window total of absolute weights, keeps the sign of
+    // negative weights
+    AbsSum,
+    // share of the largest bin, the POC is 1.0
+    Max,
+    // standard score across the bins of the window (population std)
+    Zscore,
+    // volume per unit of price, so profiles with different bin widths compare
+    Density,
+    // running share of the window total from the lowest bin up, ends at 1.0
+    Cumulative,
+}
+
+impl Normalize {
+    pub fn from_pct(pct: bool, signed: bool) -> Self {
+        match (pct, signed) {
+            (false, _) => Normalize::None,
+            (true, false) => Normalize::Sum,
+            (true, true) => Normalize::AbsSum,
+        }
+    }
+}
+
+// How `volume_quantiles` picks a price when the quantile falls between two
+// traded prices.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Interpolation {
+    // lowest price at which the cumulative volume reaches the quantile, the
+    // price at or below which that share of the volume traded
+    Lower,
+    // lowest price at which the cumulative volume exceeds the quantile
+    Higher,
+    // halfway between `Lower` and `Higher`
+    Midpoint,
+    // linear between the volume midpoints of neighbouring prices, every
+    // price stands for the middle of its own volume
+    #[default]
+    Linear,
+}
+
+// Which side of an inner bin edge is inclusive. `Left` bins are
+// [lower, upper), `Right` bins (lower, upper]. The outer edges of the grid are
+// always inclusive, so every tick of the window lands in exactly one bin.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Closed {
+    #[default]
+    Left,
+    Right,
+}
+
+impl Closed {
+    // Number of the tick size bin holding `price`, bin `n` running from
+    // `n * tick` to `(n + 1) * tick`.
+    pub fn tick_number(self, price: f64, tick: f64) -> f64 {
+        match self {
+            Closed::Left => snap(price / tick).floor(),
+            Closed::Right => snap(price / tick).ceil() - 1.0,
+        }
+    }
+}
+
+// How the rolling kernel spreads the rows over threads. `Auto` splits only
+// when there is enough work to pay for it, `Force` always splits, `Off` runs
+// every row on the calling thread.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Parallel {
+    #[default]
+    Auto,
+    Off,
+    Force,
+}
+
+// chunks per thread, so uneven groups still balance
+const CHUNKS_PER_THREAD: usize = 4;
+// every chunk replays the `window_size - 1` rows before it to warm up its
+// accumulator, `Auto` keeps that under a quarter of the chunk
+const WINDOWS_PER_CHUNK: usize = 4;
+const MIN_CHUNK_ROWS: usize = 1024;
+
+impl Parallel {
+    // Rows per chunk for `len` rows over `threads` threads, None to run
+    // serially.
+    fn chunk_rows(self, len: usize, window_size: usize, threads: usize) -> Option<usize> {
+        let balanced = len.div_ceil(threads.max(1) * CHUNKS_PER_THREAD).max(1);
+        match self {
+            Parallel::Off => None,
+            Parallel::Force => (len > 1).then_some(balanced),
+            Parallel::Auto => {
+                let rows = balanced
+                    .max(window_size.saturating_mul(WINDOWS_PER_CHUNK))
+                    .max(MIN_CHUNK_ROWS);
+                (threads > 1 && rows < len).then_some(rows)
+            }
+        }
+    }
+}
+
+// Smoothing of the binned volume of a window, applied before normalization.
+// Every bin spreads its volume over its neighbours, the share falling outside
+// the grid goes back to the bins inside, so the window total is kept.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Smoothing {
+    #[default]
+    None,
+    // Gaussian kernel, the bandwidth (standard deviation) in bins
+    Gaussian(f64),
+    // Gaussian kernel with Silverman's rule of thumb for the bandwidth
+    Silverman,
+    // equal shares over this many bins on each side
+    MovingAverage(usize),
+}
+
+// Version of the `ProfileConfig` layout, bumped when a field changes meaning.
+pub const CONFIG_VERSION: u32 = 1;
+
+// Most bins of one window. A tick size grid gets its bin count from the range
+// of the window, a tiny tick over a wide range would allocate a huge grid for
+// every window, so it fails with a `ConfigError` instead.
+pub const MAX_BINS: usize = 1_000_000;
+
+fn config_version() -> u32 {
+    CONFIG_VERSION
+}
+
+// Which price stands for a bin.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Label {
+    Lower,
+    #[default]
+    Center,
+    Upper,
+    // volume-weighted price of the ticks in the bin, the center when the bin
+    // is empty
+    Vwap,
+    // bin number from 0 at the bottom of the grid
+    Index,
+}
+
+// How a window is cut into bins: a fixed number of equal bins between the
+// lowest and the highest price, or bins of a fixed price step aligned to
+// multiples of it, so their number follows the range of the window.
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Binning {
+    Bins(usize),
+    TickSize(f64),
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ConfigError(pub String);
+
+impl std::fmt::Display for ConfigError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "invalid profile config: {}", self.0)
+    }
+}
+
+impl std::error::Error for ConfigError {}
+
+// The one configuration behind every profile, built with `new` and the
+// `with_*` methods:
+//
+//     let config = ProfileConfig::new(500, 20)
+//         .with_label(Label::Lower)
+//         .with_round(2);
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct ProfileConfig {
+    #[serde(default = "config_version")]
+    pub version: u32,
+    // rows per window, the first `window_size - 1` rows have no profile
+    pub window_size: usize,
+    pub binning: Binning,
+    #[serde(default)]
+    pub label: Label,
+    // decimals for the labels and the volume, `None` keeps full precision
+    #[serde(default)]
+    pub round: Option<u32>,
+    #[serde(default)]
+    pub normalize: Normalize,
+    #[serde(default)]
+    pub smoothing: Smoothing,
+    #[serde(default)]
+    pub closed: Closed,
+}
+
+impl ProfileConfig {
+    pub fn new(window_size: usize, bins: usize) -> Self {
+        ProfileConfig {
+            version: CONFIG_VERSION,
+            window_size,
+            binning: Binning::Bins(bins),
+            label: Label::Center,
+            round: None,
+            normalize: Normalize::None,
+            smoothing: Smoothing::None,
+            closed: Closed::Left,
+        }
+    }
+
+    pub fn with_window_size(mut self, window_size: usize) -> Self {
+        self.window_size = window_size;
+        self
+    }
+
+    pub fn with_binning(mut self, binning: Binning) -> Self {
+        self.binning = binning;
+        self
+    }
+
+    pub fn with_label(mut self, label: Label) -> Self {
+        self.label = label;
+        self
+    }
+
+    pub fn with_round(mut self, decimals: u32) -> Self {
+        self.round = Some(decimals);
+        self
+    }
+
+    pub fn with_normalize(mut self, normalize: Normalize) -> Self {
+        self.normalize = normalize;
+        self
+    }
+
+    pub fn with_smoothing(mut self, smoothing: Smoothing) -> Self {
+        self.smoothing = smoothing;
+        self
+    }
+
+    pub fn with_closed(mut self, closed: Closed) -> Self {
+        self.closed = closed;
+        self
+    }
+
+    pub fn validate(&self) -> Result<(), ConfigError> {
+        if self.version == 0 || self.version > CONFIG_VERSION {
+            return Err(ConfigError(format!(
+                "version {} is not supported, the latest is {}",
+                self.version, CONFIG_VERSION
+            )));
+        }
+        if let Smoothing::Gaussian(bandwidth) = self.smoothing {
+            if !(bandwidth.is_finite() && bandwidth > 0.0) {
+                return Err(ConfigError(format!(
+                    "gaussian bandwidth must be positive, got {bandwidth}"
+                )));
+            }
+        }
+        match self.binning {
+            Binning::Bins(0) => Err(ConfigError("bins must be at least 1".into())),
+            Binning::Bins(bins) if bins > MAX_BINS => Err(ConfigError(format!(
+                "bins must be at most {MAX_BINS}, got {bins}"
+            ))),
+            Binning::TickSize(tick_size) if !(tick_size.is_finite() && tick_size > 0.0) => Err(
+                ConfigError(format!("tick_size must be positive, got {tick_size}")),
+            ),
+            _ => Ok(()),
+        }
+    }
+}
+
+// One window: a price label and a volume per bin.
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+pub struct VolumeProfile {
+    #[serde(deserialize_with = "nan_from_null")]
+    pub price: Vec<f64>,
+    #[serde(deserialize_with = "nan_from_null")]
+    pub volume: Vec<f64>,
+    // bin of the POC, taken from the volume before it is normalized and
+    // rounded, which can move the largest bin (e.g. `Cumulative`)
+    #[serde(default)]
+    pub poc_bin: Option<usize>,
+}
+
+impl VolumeProfile {
+    // Profile of a single window of ticks, `None` when no tick has a price.
+    pub fn from_window(
+        price: &[f64],
+        volume: &[f64],
+        config: &ProfileConfig,
+    ) -> Result<Option<Self>, ConfigError> {
+        config.validate()?;
+        let Some(grid) = Grid::from_prices(price, config.binning, config.closed)? else {
+            return Ok(None);
+        };
+        let labels = grid.labels(config.label, price, volume, config.round);
+        let mut volume = grid.sum(price, volume);
+        smooth(&mut volume, config.smoothing, priced(price));
+        let poc_bin = poc_index(&volume);
+        normalize(&mut volume, config.normalize, grid.interval, config.round);
+        Ok(Some(VolumeProfile {
+            price: labels,
+            volume,
+            poc_bin: Some(poc_bin),
+        }))
+    }
+
+    // Price of the bin with the most volume, the lowest one on ties. A profile
+    // without `poc_bin`, e.g. one built by hand, takes the largest bin of
+    // `volume` as it is.
+    pub fn poc(&self) -> Option<f64> {
+        if self.volume.is_empty() {
+            return None;
+        }
+        let bin = self.poc_bin.unwrap_or_else(|| poc_index(&self.volume));
+        self.price.get(bin).copied()
+    }
+}
+
+// `bins` bins of `interval` from `min` to `max`, inclusive on the side given
+// by `closed` (see `Closed`). Prices outside [min, max] are in no bin.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Grid {
+    pub min: f64,
+    pub max: f64,
+    pub interval: f64,
+    pub bins: usize,
+    pub closed: Closed,
+}
+
+impl Grid {
+    pub fn new(min: f64, interval: f64, bins: usize) -> Self {
+        Grid {
+            min,
+            max: min + bins as f64 * interval,
+            interval,
+            bins,
+            closed: Closed::Left,
+        }
+    }
+
+    // `bins` equal bins from `min` to exactly `max`.
+    pub fn spanning(min: f64, max: f64, bins: usize) -> Self {
+        Grid {
+            max,
+            ..Grid::new(min, (max - min) / bins as f64, bins)
+        }
+    }
+
+    pub fn with_closed(mut self, closed: Closed) -> Self {
+        self.closed = closed;
+        self
+    }
+
+    // `None` when every price is missing, an error for fewer than one bin, a
+    // tick size that is not positive or a grid that would need more than
+    // `MAX_BINS` bins. Tick size bins sit on
+    // multiples of the tick: with `Closed::Left` the lowest price is in the
+    // first bin's [lower, upper), with `Closed::Right` the highest price is in
+    // the last bin's (lower, upper].
+    pub fn from_prices(
+        price: &[f64],
+        binning: Binning,
+        closed: Closed,
+    ) -> Result<Option<Self>, ConfigError> {
+        match binning {
+            Binning::Bins(0) => return Err(ConfigError("bins must be at least 1".into())),
+            Binning::TickSize(tick) if !(tick.is_finite() && tick > 0.0) => {
+                return Err(ConfigError(format!(
+                    "tick_size must be positive, got {tick}"
+                )))
+            }
+            _ => {}
+        }
+        let Some((min, max)) = price.iter().filter(|p| !p.is_nan()).fold(
+            None,
+            |acc: Option<(f64, f64)>, &p| match acc {
+                Some((min, max)) => Some((min.min(p), max.max(p))),
+                None => Some((p, p)),
+            },
+        ) else {
+            return Ok(None);
+        };
+        let grid = match binning {
+            Binning::Bins(bins) if bins > MAX_BINS => {
+                return Err(ConfigError(format!(
+                    "bins must be at most {MAX_BINS}, got {bins}"
+                )))
+            }
+            Binning::Bins(bins) => Grid::spanning(min, max, bins),
+            Binning::TickSize(tick) => {
+                // tick numbers of the first and the last bin
+                let (first, last) = (closed.tick_number(min, tick), closed.tick_number(max, tick));
+                let bins = last - first + 1.0;
+                // also catches an infinite price
+                if !bins.is_finite() || bins > MAX_BINS as f64 {
+                    return Err(ConfigError(format!(
+                        "tick_size {tick} gives {bins} bins over the window range {min} to {max}, \
+                         more than {MAX_BINS}"
+                    )));
+                }
+                Grid::new(first * tick, tick, bins as usize)
+            }
+        };
+        Ok(Some(grid.with_closed(closed)))
+    }
+
+    // Lower edge of bin `n`, `lower(bins)` is the upper edge of the grid.
+    pub fn lower(&self, n: usize) -> f64 {
+        if n >= self.bins {
+            self.max
+        } else {
+            self.min + n as f64 * self.interval
+        }
+    }
+
+    pub fn upper(&self, n: usize) -> f64 {
+        self.lower(n + 1)
+    }
+
+    // The bin holding `price`, `None` outside the grid or for a missing
+    // price. The bin number is the offset from `min` in intervals, so every
+    // price lands in exactly one bin and a higher price never in a lower bin.
+    // An offset on a whole number is an edge and goes to the closed side of
+    // it. A grid without range puts its one price in the bin on its closed
+    // outer edge. A grid without bins holds no price.
+    pub fn bin(&self, price: f64) -> Option<usize> {
+        let last = self.bins.checked_sub(1)?;
+        let offset = snap((price - self.min) / self.interval);
+        if !offset.is_finite() {
+            let edge = match self.closed {
+                Closed::Left => last,
+                Closed::Right => 0,
+            };
+            return (price == self.min).then_some(edge);
+        }
+        if !(0.0..=self.bins as f64).contains(&offset) {
+            return None;
+        }
+        let n = match self.closed {
+            Closed::Left => offset.floor() as usize,
+            Closed::Right => (offset.ceil() as usize).saturating_sub(1),
+        };
+        Some(n.min(last))
+    }
+
+    // One label per bin. `price` and `weight` are the ticks of the window,
+    // only read for `Label::Vwap`, which weights by the absolute weight.
+    pub fn labels(
+        &self,
+        label: Label,
+        price: &[f64],
+        weight: &[f64],
+        round: Option<u32>,
+    ) -> Vec<f64> {
+        let vwap = (label == Label::Vwap).then(|| {
+            let abs_weight: Vec<f64> = weight.iter().map(|w| w.abs()).collect();
+            let notional: Vec<f64> = price.iter().zip(&abs_weight).map(|(p, w)| p * w).collect();
+            (self.sum(price, &notional), self.sum(price, &abs_weight))
+        });
+        (0..self.bins)
+            .map(|n| {
+                let center = (self.lower(n) + self.upper(n)) / 2.0;
+                let label = match label {
+                    Label::Lower => self.lower(n),
+                    Label::Center => center,
+                    Label::Upper => self.upper(n),
+                    Label::Index => n as f64,
+                    Label::Vwap => match &vwap {
+                        Some((notional, weight)) if weight[n] > 0.0 => notional[n] / weight[n],
+                        _ => center,
+                    },
+                };
+                round_to(label, round)
+            })
+            .collect()
+    }
+
+    // Sum `weight` into the bins, missing prices and NaN weights are skipped.
+    pub fn sum(&self, price: &[f64], weight: &[f64]) -> Vec<f64> {
+        self.sum_bins(price.iter().map(|&p| self.bin(p)), weight)
+    }
+
+    // Number of distinct `period` codes trading in every bin.
+    pub fn distinct(&self, price: &[f64], period: &[u32]) -> Vec<f64> {
+        self.distinct_bins(price.iter().map(|&p| self.bin(p)), period)
+    }
+
+    // `sum` over bins already looked up, so the columns of a window bin the prices once.
+    fn sum_bins(&self, bins: impl IntoIterator<Item = Option<usize>>, weight: &[f64]) -> Vec<f64> {
+        let mut out = vec![0.0; self.bins];
+        for (bin, &w) in bins.into_iter().zip(weight) {
+            if let Some(n) = bin {
+                if !w.is_nan() {
+                    out[n] += w;
+                }
+            }
+        }
+        out
+    }
+
+    fn distinct_bins(
+        &self,
+        bins: impl IntoIterator<Item = Option<usize>>,
+        period: &[u32],
+    ) -> Vec<f64> {
+        let mut seen: Vec<Vec<u32>> = vec![vec![]; self.bins];
+        for (bin, &code) in bins.into_iter().zip(period) {
+            if let Some(n) = bin {
+                seen[n].push(code);
+            }
+        }
+        seen.into_iter()
+            .map(|mut codes| {
+                codes.sort_unstable();
+                codes.dedup();
+                codes.len() as f64
+            })
+            .collect()
+    }
+}
+
+// Round half away from zero to `decimals`, like polars' `round`.
+pub fn round_to(value: f64, decimals: Option<u32>) -> f64 {
+    match decimals {
+        Some(0) => value.round(),
+        Some(decimals) => {
+            let multiplier = 10f64.powf(decimals as f64);
+            (value * multiplier).round() / multiplier
+        }
+        None => value,
+    }
+}
+
+// Scale the binned volume of one window in place, then round it. `bin_width`
+// is the price step of the bins, used by `Density`. Degenerate windows (no
+// volume, a single price, equal bins for `Zscore`) divide by zero and give NaN
+// or infinity, like `pct` always did.
+pub fn normalize(volume: &mut [f64], normalize: Normalize, bin_width: f64, round: Option<u32>) {
+    let n = volume.len() as f64;
+    match normalize {
+        Normalize::None => {}
+        Normalize::Sum => {
+            let total: f64 = volume.iter().sum();
+            volume.iter_mut().for_each(|v| *v /= total);
+        }
+        Normalize::AbsSum => {
+            let total: f64 = volume.iter().map(|v| v.abs()).sum();
+            volume.iter_mut().for_each(|v| *v /= total);
+        }
+        Normalize::Max => {
+            let max = volume.iter().copied().fold(f64::NEG_INFINITY, f64::max);
+            volume.iter_mut().for_each(|v| *v /= max);
+        }
+        Normalize::Zscore => {
+            let mean = volume.iter().sum::<f64>() / n;
+            let var = volume.iter().map(|v| (v - mean).powi(2)).sum::<f64>() / n;
+            let std = var.sqrt();
+            volume.iter_mut().for_each(|v| *v = (*v - mean) / std);
+        }
+        Normalize::Density => {
+            volume.iter_mut().for_each(|v| *v /= bin_width);
+        }
+        Normalize::Cumulative => {
+            let total: f64 = volume.iter().sum();
+            let mut running = 0.0;
+            for v in volume.iter_mut() {
+                running += *v;
+                *v = running / total;
+            }
+        }
+    }
+    volume.iter_mut().for_each(|v| *v = round_to(*v, round));
+}
+
+// JSON for other languages and humans, bincode as the compact binary format
+// for storage and messages between services. JSON has no NaN (e.g. a `pct`
+// profile of a window without volume, or a missing price), it is written as
+// null and read back as NaN.
+pub trait ProfileEncoding: Serialize + DeserializeOwned {
+    fn to_json(&self) -> serde_json::Result<String> {
+        serde_json::to_string(self)
+    }
+
+    fn from_json(json: &str) -> serde_json::Result<Self> {
+        serde_json::from_str(json)
+    }
+
+    fn to_bytes(&self) -> bincode::Result<Vec<u8>> {
+        bincode::serialize(self)
+    }
+
+    fn from_bytes(bytes: &[u8]) -> bincode::Result<Self> {
+        bincode::deserialize(bytes)
+    }
+}
+
+// serde_json writes NaN as null, read the nulls of human readable formats
+// back as NaN. Binary formats keep NaN as it is.
+fn nan_from_null<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Vec<f64>, D::Error> {
+    if !deserializer.is_human_readable() {
+        return Vec::deserialize(deserializer);
+    }
+    let values: Vec<Option<f64>> = Vec::deserialize(deserializer)?;
+    Ok(values.into_iter().map(|v| v.unwrap_or(f64::NAN)).collect())
+}
+
+fn nan_columns_from_null<'de, D: Deserializer<'de>>(
+    deserializer: D,
+) -> Result<Vec<Vec<f64>>, D::Error> {
+    if !deserializer.is_human_readable() {
+        return Vec::deserialize(deserializer);
+    }
+    let columns: Vec<Vec<Option<f64>>> = Vec::deserialize(deserializer)?;
+    Ok(columns
+        .into_iter()
+        .map(|column| column.into_iter().map(|v| v.unwrap_or(f64::NAN)).collect())
+        .collect())
+}
+
+impl ProfileEncoding for ProfileConfig {}
+impl ProfileEncoding for VolumeProfile {}
+// the live window, to checkpoint and restore a running accumulator
+impl ProfileEncoding for ProfileAccumulator {}
+
+// Index of the bin with the most volume, the lowest one on ties.
+pub fn poc_index(volume_at_price: &[f64]) -> usize {
+    volume_at_price.iter().enumerate().fold(
+        0,
+        |best, (n, v)| {
+            if *v > volume_at_price[best] {
+                n
+            } else {
+                best
+            }
+        },
+    )
+}
+
+// Lowest and highest bin of the value area: starting from the POC, keep adding
+// the neighbouring bin with more volume until `value_area` of the total is covered.
+pub fn value_area_index(volume_at_price: &[f64], value_area: f64) -> (usize, usize) {
+    value_area_around(
+        |n| volume_at_price[n],
+        volume_at_price.len(),
+        poc_index(volume_at_price),
+        volume_at_price.iter().sum(),
+        value_area,
+    )
+}
+
+// `value_area_index` for a histogram of `bins` bins read through `volume`,
+// with its POC and total already known, so only the bins of the value area
+// are read.
+pub(crate) fn value_area_around(
+    volume: impl Fn(usize) -> f64,
+    bins: usize,
+    poc: usize,
+    total: f64,
+    value_area: f64,
+) -> (usize, usize) {
+    let target = total * value_area;
+    let (mut low, mut high) = (poc, poc);
+    let mut covered = volume(poc);
+    while covered < target && (low > 0 || high < bins - 1) {
+        let below = if low > 0 {
+            volume(low - 1)
+        } else {
+            f64::NEG_INFINITY
+        };
+        let above = if high < bins - 1 {
+            volume(high + 1)
+        } else {
+            f64::NEG_INFINITY
+        };
+        if above >= below {
+            high += 1;
+            covered += above;
+        } else {
+            low -= 1;
+            covered += below;
+        }
+    }
+    (low, high)
+}
+
+// `x` as the nearest whole number when it is only float noise away from it,
+// so a price on a bin edge counts as on the edge and not an ulp beside it.
+fn snap(x: f64) -> f64 {
+    let whole = x.round();
+    if (x - whole).abs() <= 1e-9 * whole.abs().max(1.0) {
+        whole
+    } else {
+        x
+    }
+}
+
+// Number of ticks with a price, the sample size for `Smoothing::Silverman`.
+pub(crate) fn priced(price: &[f64]) -> usize {
+    price.iter().filter(|p| !p.is_nan()).count()
+}
+
+// Smooth the binned volume of a window of `ticks` ticks in place, see
+// `Smoothing`. Silverman's bandwidth comes from the spread of the binned
+// volume, 0.9 * min(std, IQR / 1.34) * ticks^(-1/5) in bins, and leaves the
+// volume as it is when the window has no spread.
+pub fn smooth(volume_at_price: &mut [f64], smoothing: Smoothing, ticks: usize) {
+    let bandwidth = match smoothing {
+        Smoothing::None => return,
+        Smoothing::Gaussian(bandwidth) => bandwidth,
+        Smoothing::Silverman => silverman_bandwidth(volume_at_price, ticks),
+        Smoothing::MovingAverage(0) => return,
+        Smoothing::MovingAverage(radius) => radius as f64,
+    };
+    if !(bandwidth.is_finite() && bandwidth > 0.0) {
+        return;
+    }
+    // kernel weight by distance in bins
+    let kernel: Vec<f64> = match smoothing {
+        Smoothing::MovingAverage(radius) => vec![1.0; radius + 1],
+        _ => {
+            let reach = (4.0 * bandwidth).ceil() as usize;
+            (0..=reach)
+                .map(|d| (-0.5 * (d as f64 / bandwidth).powi(2)).exp())
+                .collect()
+        }
+    };
+    let n = volume_at_price.len();
+    let reach = kernel.len() - 1;
+    let mut smoothed = vec![0.0; n];
+    for (k, v) in volume_at_price.iter().enumerate() {
+        if *v == 0.0 || v.is_nan() {
+            continue;
+        }
+        let (first, last) = (k.saturating_sub(reach), (k + reach).min(n - 1));
+        let mass: f64 = (first..=last).map(|i| kernel[i.abs_diff(k)]).sum();
+        for (i, s) in smoothed.iter_mut().enumerate().take(last + 1).skip(first) {
+            *s += v * kernel[i.abs_diff(k)] / mass;
+        }
+    }
+    volume_at_price.copy_from_slice(&smoothed);
+}
+
+fn silverman_bandwidth(volume_at_price: &[f64], ticks: usize) -> f64 {
+    let index: Vec<f64> = (0..volume_at_price.len()).map(|k| k as f64).collect();
+    let weight: Vec<f64> = volume_at_price.iter().map(|v| v.abs()).collect();
+    let total: f64 = weight.iter().sum();
+    let mean = index.iter().zip(&weight).map(|(k, w)| k * w).sum::<f64>() / total;
+    let var = index
+        .iter()
+        .zip(&weight)
+        .map(|(k, w)| w * (k - mean).powi(2))
+        .sum::<f64>()
+        / total;
+    let spread = match volume_quantiles(&index, &weight, &[0.25, 0.75], Interpolation::Linear) {
+        Some(q) if q[1] > q[0] => var.sqrt().min((q[1] - q[0]) / 1.34),
+        _ => var.sqrt(),
+    };
+    0.9 * spread * (ticks as f64).powf(-0.2)
+}
+
+// Volume-weighted price quantiles of one window, exact on the raw ticks. Ticks
+// with a NaN price or without positive volume are skipped, equal prices are
+// merged, None when no volume is left. `probs` are shares in [0, 1].
+pub fn volume_quantiles(
+    price: &[f64],
+    volume: &[f64],
+    probs: &[f64],
+    interpolation: Interpolation,
+) -> Option<Vec<f64>> {
+    let mut ticks: Vec<(f64, f64)> = price
+        .iter()
+        .zip(volume)
+        .filter(|(p, v)| !p.is_nan() && **v > 0.0)
+        .map(|(p, v)| (*p, *v))
+        .collect();
+    if ticks.is_empty() {
+        return None;
+    }
+    ticks.sort_by(|a, b| a.0.total_cmp(&b.0));
+    // distinct prices, the volume traded up to and including each one, and
+    // the middle of each price's own volume
+    let mut levels: Vec<f64> = vec![];
+    let mut cumulative: Vec<f64> = vec![];
+    let mut total = 0.0;
+    for (p, v) in ticks {
+        total += v;
+        if levels.last() == Some(&p) {
+            *cumulative.last_mut().unwrap() = total;
+        } else {
+            levels.push(p);
+            cumulative.push(total);
+        }
+    }
+    let midpoints: Vec<f64> = cumulative
+        .iter()
+        .scan(0.0, |below, c| {
+            let mid = (*below + c) / 2.0;
+            *below = *c;
+            Some(mid)
+        })
+        .collect();
+    let last = levels.len() - 1;
+    let quantile = |prob: f64| {
+        let target = prob * total;
+        let lower = cumulative.partition_point(|c| *c < target).min(last);
+        let higher = cumulative.partition_point(|c| *c <= target).min(last);
+        match interpolation {
+            Interpolation::Lower => levels[lower],
+            Interpolation::Higher => levels[higher],
+            Interpolation::Midpoint => (levels[lower] + levels[higher]) / 2.0,
+            Interpolation::Linear => {
+                let next = midpoints.partition_point(|m| *m <= target);
+                if next == 0 {
+                    levels[0]
+                } else if next > last {
+                    levels[last]
+                } else {
+                    let frac =
+                        (target - midpoints[next - 1]) / (midpoints[next] - midpoints[next - 1]);
+                    levels[next - 1] + frac * (levels[next] - levels[next - 1])
+                }
+            }
+        }
+    };
+    Some(probs.iter().map(|prob| quantile(*prob)).collect())
+}
+
+// Stateful profile for live use: push ticks as they arrive and take the same
+// profile `rolling_profile` gives for the row of the last tick. With a
+// `window_size` the oldest tick is evicted on every push once the window is
+// full. With a `window_size` of 0 the window is unbounded and only shrinks
+// through `evict` / `evict_before`, e.g. for a time based window.
+#[derive(Clone, Debug, Deserialize)]
+#[serde(try_from = "AccumulatorState")]
+pub struct ProfileAccumulator {
+    config: ProfileConfig,
+    // ticks before `start` are evicted, they are dropped in bulk so eviction
+    // stays O(1) and the live ticks stay one contiguous slice
+    start: usize,
+    price: Vec<f64>,
+    ts: Vec<i64>,
+    weights: Vec<Vec<f64>>,
+    period: Option<Vec<u32>>,
+}
+
+// A checkpoint of a `ProfileAccumulator` as it is read. Only the live ticks
+// are written, with a `start` of 0, but older checkpoints still carry evicted
+// ticks before `start`. Payloads from elsewhere can be anything, so the
+// columns are checked before they become an accumulator.
+#[derive(Deserialize)]
+struct AccumulatorState {
+    config: ProfileConfig,
+    #[serde(default)]
+    start: usize,
+    #[serde(deserialize_with = "nan_from_null")]
+    price: Vec<f64>,
+    ts: Vec<i64>,
+    #[serde(deserialize_with = "nan_columns_from_null")]
+    weights: Vec<Vec<f64>>,
+    period: Option<Vec<u32>>,
+}
+
+// The live part of a `ProfileAccumulator` as it is written, the same layout
+// as `AccumulatorState`.
+#[derive(Serialize)]
+struct LiveState<'a> {
+    config: &'a ProfileConfig,
+    start: usize,
+    price: &'a [f64],
+    ts: &'a [i64],
+    weights: Vec<&'a [f64]>,
+    period: Option<&'a [u32]>,
+}
+
+impl Serialize for ProfileAccumulator {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let start = self.start;
+        LiveState {
+            config: &self.config,
+            start: 0,
+            price: &self.price[start..],
+            ts: &self.ts[start..],
+            weights: self.weights.iter().map(|w| &w[start..]).collect(),
+            period: self.period.as_ref().map(|p| &p[start..]),
+        }
+        .serialize(serializer)
+    }
+}
+
+impl TryFrom<AccumulatorState> for ProfileAccumulator {
+    type Error = String;
+
+    fn try_from(state: AccumulatorState) -> Result<Self, String> {
+        let AccumulatorState {
+            config,
+            start,
+            mut price,
+            mut ts,
+            mut weights,
+            mut period,
+        } = state;
+        config.validate().map_err(|e| e.to_string())?;
+        let len = price.len();
+        if start > len {
+            return Err(format!("start {start} is past the {len} ticks"));
+        }
+        if weights.is_empty() {
+            return Err("an accumulator needs at least one weight column".into());
+        }
+        let mut lengths = std::iter::once(ts.len())
+            .chain(weights.iter().map(Vec::len))
+            .chain(period.iter().map(Vec::len));
+        if let Some(other) = lengths.find(|&n| n != len) {
+            return Err(format!("a column has {other} ticks, the price has {len}"));
+        }
+        let window_size = config.window_size;
+        if window_size > 0 && len - start > window_size {
+            return Err(format!(
+                "{} live ticks do not fit a window of {window_size}",
+                len - start
+            ));
+        }
+        price.drain(..start);
+        ts.drain(..start);
+        for column in weights.iter_mut() {
+            column.drain(..start);
+        }
+        if let Some(column) = period.as_mut() {
+            column.drain(..start);
+        }
+        Ok(ProfileAccumulator {
+            config,
+            start: 0,
+            price,
+            ts,
+            weights,
+            period,
+        })
+    }
+}
+
+impl ProfileAccumulator {
+    // Fails with the `ConfigError` of an invalid config.
+    pub fn new(config: ProfileConfig) -> Result<Self, ConfigError> {
+        config.validate()?;
+        Ok(ProfileAccumulator::with_columns(config, 1, false))
+    }
+
+    // `n_weights` weight columns per tick, and with `period` a period code per
+    // tick whose distinct count replaces the last column. The config is not
+    // validated, see `new`.
+    pub(crate) fn with_columns(config: ProfileConfig, n_weights: usize, period: bool) -> Self {
+        ProfileAccumulator {
+            config,
+            start: 0,
+            price: vec![],
+            ts: vec![],
+            weights: vec![vec![]; n_weights],
+            period: period.then(Vec::new),
+        }
+    }
+
+    pub fn config(&self) -> &ProfileConfig {
+        &self.config
+    }
+
+    pub fn len(&self) -> usize {
+        self.price.len() - self.start
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    // Whether the window is full, only then there is a profile, like the
+    // first `window_size - 1` rows of `rolling_profile`.
+    pub fn is_ready(&self) -> bool {
+        match self.config.window_size {
+            0 => !self.is_empty(),
+            window_size => self.len() == window_size,
+        }
+    }
+
+    pub fn push(&mut self, price: f64, volume: f64, ts: i64) {
+        self.push_row(price, &[volume], None, ts)
+    }
+
+    pub(crate) fn push_row(&mut self, price: f64, weights: &[f64], period: Option<u32>, ts: i64) {
+        self.price.push(price);
+        self.ts.push(ts);
+        for (column, &w) in self.weights.iter_mut().zip(weights) {
+            column.push(w);
+        }
+        if let (Some(column), Some(code)) = (self.period.as_mut(), period) {
+            column.push(code);
+        }
+        if self.config.window_size > 0 && self.len() > self.config.window_size {
+            self.evict();
+        }
+    }
+
+    // Drop the oldest tick, false when there is none.
+    pub fn evict(&mut self) -> bool {
+        if self.is_empty() {
+            return false;
+        }
+        self.start += 1;
+        if self.start >= 1024 && self.start * 2 >= self.price.len() {
+            let start = self.start;
+            self.price.drain(..start);
+            self.ts.drain(..start);
+            for column in self.weights.iter_mut() {
+                column.drain(..start);
+            }
+            if let Some(column) = self.period.as_mut() {
+                column.drain(..start);
+            }
+            self.start = 0;
+        }
+        true
+    }
+
+    // Drop every tick older than `ts`, returns how many were dropped.
+    pub fn evict_before(&mut self, ts: i64) -> usize {
+        let mut evicted = 0;
+        while !self.is_empty() && self.ts[self.start] < ts {
+            self.evict();
+            evicted += 1;
+        }
+        evicted
+    }
+
+    pub fn clear(&mut self) {
+        while self.evict() {}
+    }
+
+    fn grid(&self) -> Result<Option<Grid>, ConfigError> {
+        if !self.is_ready() {
+            return Ok(None);
+        }
+        Grid::from_prices(
+            &self.price[self.start..],
+            self.config.binning,
+            self.config.closed,
+        )
+    }
+
+    // Labels and one binned vector per weight column, after normalization.
+    pub(crate) fn snapshot_bins(&self) -> Result<Option<WindowBins>, ConfigError> {
+        let Some(grid) = self.grid()? else {
+            return Ok(None);
+        };
+        let price = &self.price[self.start..];
+        // bin every tick once, the weight columns all add onto the same bins
+        let bins: Vec<Option<usize>> = price.iter().map(|&p| grid.bin(p)).collect();
+        // tpo: distinct periods trading in the bin, in place of the last column
+        let summed = self.weights.len() - self.period.is_some() as usize;
+        let mut binned: Vec<Vec<f64>> = self.weights[..summed]
+            .iter()
+            .map(|w| grid.sum_bins(bins.iter().copied(), &w[self.start..]))
+            .collect();
+        if let Some(period) = &self.period {
+            binned.push(grid.distinct_bins(bins.iter().copied(), &period[self.start..]));
+        }
+        for v in binned.iter_mut() {
+            smooth(v, self.config.smoothing, priced(price));
+        }
+        let poc = poc_index(&binned[0]);
+        for v in binned.iter_mut() {
+            normalize(v, self.config.normalize, grid.interval, self.config.round);
+        }
+        Ok(Some(WindowBins {
+            grid,
+            poc,
+            labels: grid.labels(
+                self.config.label,
+                price,
+                &self.weights[0][self.start..],
+                self.config.round,
+            ),
+            binned,
+        }))
+    }
+
+    // The profile of the current window, `None` until the window is full or
+    // when its grid would exceed `MAX_BINS`, see `try_snapshot`.
+    pub fn snapshot(&self) -> Option<VolumeProfile> {
+        self.try_snapshot().ok().flatten()
+    }
+
+    // `snapshot` with the error of a grid over `MAX_BINS`.
+    pub fn try_snapshot(&self) -> Result<Option<VolumeProfile>, ConfigError> {
+        Ok(self.snapshot_bins()?.map(WindowBins::into_profile))
+    }
+
+    // Volume per bin of the first weight column, smoothed but not normalized.
+    fn volume_at_price(&self) -> Option<(Grid, Vec<f64>)> {
+        let grid = self.grid().ok()??;
+        let price = &self.price[self.start..];
+        let mut volume = grid.sum(price, &self.weights[0][self.start..]);
+        smooth(&mut volume, self.config.smoothing, priced(price));
+        Some((grid, volume))
+    }
+
+    // Label of the bin with the most volume.
+    pub fn poc(&self) -> Option<f64> {
+        let (grid, volume) = self.volume_at_price()?;
+        let labels = grid.labels(
+            self.config.label,
+            &self.price[self.start..],
+            &self.weights[0][self.start..],
+            self.config.round,
+        );
+        Some(labels[poc_index(&volume)])
+    }
+
+    // Lower edge of the lowest and upper edge of the highest bin holding
+    // `value_area` (e.g. 0.7) of the window volume.
+    pub fn value_area(&self, value_area: f64) -> Option<(f64, f64)> {
+        let (grid, volume) = self.volume_at_price()?;
+        let (low, high) = value_area_index(&volume, value_area);
+        Some((grid.lower(low), grid.upper(high)))
+    }
+}
+
+// One window: its grid, the labels and one binned vector per weight column.
+pub(crate) struct WindowBins {
+    // only read by the plugin expressions
+    #[cfg_attr(not(feature = "python"), allow(dead_code))]
+    pub grid: Grid,
+    pub labels: Vec<f64>,
+    pub binned: Vec<Vec<f64>>,
+    // POC bin of the first column before normalization
+    pub poc: usize,
+}
+
+impl WindowBins {
+    fn into_profile(mut self) -> VolumeProfile {
+        VolumeProfile {
+            price: self.labels,
+            volume: self.binned.swap_remove(0),
+            poc_bin: Some(self.poc),
+        }
+    }
+}
+
+pub(crate) type RollingBins = Vec<Option<WindowBins>>;
+
+// The rolling kernel behind every entry point: an accumulator per chunk of
+// rows, warmed up with the ticks the first window of the chunk reaches back
+// to. Windows never reach back past the start of their group in
+// `group_offsets` (the first row of the group each row belongs to). With
+// `period` the last weight column is not summed, its bins count the distinct
+// periods instead.
+pub(crate) fn rolling_bins(
+    price: &[f64],
+    weights: &[&[f64]],
+    period: Option<&[u32]>,
+    group_offsets: Option<&[usize]>,
+    config: &ProfileConfig,
+    parallel: Parallel,
+) -> Result<RollingBins, ConfigError> {
+    rolling_map(
+        price,
+        weights,
+        period,
+        group_offsets,
+        config,
+        parallel,
+        |row| row,
+    )
+}
+
+// `rolling_bins` with every window handed to `f` as soon as it is binned, so
+// the caller keeps what it needs of a window rather than all of its bins.
+pub(crate) fn rolling_map<T: Send>(
+    price: &[f64],
+    weights: &[&[f64]],
+    period: Option<&[u32]>,
+    group_offsets: Option<&[usize]>,
+    config: &ProfileConfig,
+    parallel: Parallel,
+    f: impl Fn(Option<WindowBins>) -> T + Send + Sync,
+) -> Result<Vec<T>, ConfigError> {
+    config.validate()?;
+    let window_size = config.window_size;
+    let price_len = price.len();
+    if window_size == 0 {
+        return Ok((0..price_len).map(|_| f(None)).collect());
+    }
+    let group_offset = |row: usize| group_offsets.map_or(0, |offsets| offsets[row]);
+    // rows `first..end` (0 based)
+    let rows = |first: usize, end: usize| {
+        let mut acc =
+            ProfileAccumulator::with_columns(config.clone(), weights.len(), period.is_some());
+        let mut row_weights = vec![0.0; weights.len()];
+        let mut out = Vec::with_capacity(end.saturating_sub(first));
+        if first >= end {
+            return Ok(out);
+        }
+        let from = first
+            .saturating_sub(window_size - 1)
+            .max(group_offset(first));
+        for row in from..end {
+            if row > from && group_offset(row) != group_offset(row - 1) {
+                acc.clear();
+            }
+            for (w, column) in row_weights.iter_mut().zip(weights) {
+                *w = column[row];
+            }
+            let code = period.map(|p| p[row]);
+            acc.push_row(price[row], &row_weights, code, row as i64);
+            if row >= first {
+                out.push(f(acc.snapshot_bins()?));
+            }
+        }
+        Ok(out)
+    };
+
+    let threads = rayon::current_num_threads();
+    let Some(chunk_rows) = parallel.chunk_rows(price_len, window_size, threads) else {
+        return rows(0, price_len);
+    };
+    let chunks = (0..price_len.div_ceil(chunk_rows))
+        .into_par_iter()
+        .map(|chunk| {
+            let first = chunk * chunk_rows;
+            rows(first, (first + chunk_rows).min(price_len))
+        })
+        .collect::<Result<Vec<Vec<T>>, ConfigError>>()?;
+    Ok(chunks.into_iter().flatten().collect())
+}
+
+// `f` of every row in `0..len` for rows that do not depend on each other,
+// split over the current rayon pool like the rolling kernel with a window of
+// one row.
+#[cfg_attr(not(feature = "python"), allow(dead_code))]
+pub(crate) fn map_rows<T: Send>(
+    len: usize,
+    parallel: Parallel,
+    f: impl Fn(usize) -> T + Send + Sync,
+) -> Vec<T> {
+    let threads = rayon::current_num_threads();
+    let Some(chunk_rows) = parallel.chunk_rows(len, 1, threads) else {
+        return (0..len).map(f).collect();
+    };
+    (0..len)
+        .into_par_iter()
+        .with_min_len(chunk_rows)
+        .map(f)
+        .collect()
+}
+
+// Rolling profile of the last `config.window_size` ticks at every row.
+pub fn rolling_profile(
+    price: &[f64],
+    volume: &[f64],
+    config: &ProfileConfig,
+) -> Result<Vec<Option<VolumeProfile>>, ConfigError> {
+    rolling_profile_with(price, volume, config, Parallel::Auto)
+}
+
+// `rolling_profile` with the threading picked by `parallel`. The chunks run
+// on the current rayon pool, call it inside `ThreadPool::install` to use
+// another one.
+pub fn rolling_profile_with(
+    price: &[f64],
+    volume: &[f64],
+    config: &ProfileConfig,
+    parallel: Parallel,
+) -> Result<Vec<Option<VolumeProfile>>, ConfigError> {
+    Ok(
+        rolling_bins(price, &[volume], None, None, config, parallel)?
+            .into_iter()
+            .map(|row| row.map(WindowBins::into_profile))
+            .collect(),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn config() -> ProfileConfig {
+        ProfileConfig::new(3, 4)
+            .with_label(Label::Lower)
+            .with_round(3)
+            .with_normalize(Normalize::AbsSum)
+    }
+
+    #[test]
+    fn config_round_trip() {
+        let config = config();
+        let json = config.to_json().unwrap();
+        assert_eq!(
+            json,
+            r#"{"version":1,"window_size":3,"binning":{"bins":4},"label":"lower","round":3,"normalize":"abs_sum","smoothing":"none","closed":"left"}"#
+        );
+        assert_eq!(ProfileConfig::from_json(&json).unwrap(), config);
+        let smoothed = config.clone().with_smoothing(Smoothing::Gaussian(1.5));
+        assert!(smoothed
+            .to_json()
+            .unwrap()
+            .contains(r#""smoothing":{"gaussian":1.5}"#));
+        let bytes = config.to_bytes().unwrap();
+        assert_eq!(ProfileConfig::from_bytes(&bytes).unwrap(), config);
+        // the optional fields fall back to their defaults
+        let minimal = r#"{"window_size":3,"binning":{"tick_size":0.25}}"#;
+        assert_eq!(
+            ProfileConfig::from_json(minimal).unwrap(),
+            ProfileConfig::new(3, 1).with_binning(Binning::TickSize(0.25))
+        );
+    }
+
+    #[test]
+    fn config_validation() {
+        assert!(config().validate().is_ok());
+        assert!(ProfileConfig::new(3, 0).validate().is_err());
+        assert!(ProfileConfig::new(3, MAX_BINS + 1).validate().is_err());
+        let tick = ProfileConfig::new(3, 1).with_binning(Binning::TickSize(-0.5));
+        assert!(tick.validate().is_err());
+        let mut future = config();
+        future.version = CONFIG_VERSION + 1;
+        assert!(future.validate().is_err());
+
+        // every entry point checks the config before binning
+        let (price, volume) = ([1.0, 2.0, 3.0], [1.0; 3]);
+        for bad in [ProfileConfig::new(3, 0), tick] {
+            assert!(rolling_profile(&price, &volume, &bad).is_err());
+            assert!(VolumeProfile::from_window(&price, &volume, &bad).is_err());
+            assert!(ProfileAccumulator::new(bad.clone()).is_err());
+            assert!(Grid::from_prices(&price, bad.binning, Closed::Left).is_err());
+        }
+        assert_eq!(Grid::new(1.0, 0.5, 0).bin(1.0), None);
+    }
+
+    #[test]
+    fn normalize_modes() {
+        let normalized = |mode| {
+            let mut volume = [1.0, 4.0, 2.0, 1.0];
+            normalize(&mut volume, mode, 0.5, Some(4));
+            volume
+        };
+        assert_eq!(normalized(Normalize::Sum), [0.125, 0.5, 0.25, 0.125]);
+        assert_eq!(normalized(Normalize::Max), [0.25, 1.0, 0.5, 0.25]);
+        assert_eq!(normalized(Normalize::Density), [2.0, 8.0, 4.0, 2.0]);
+        assert_eq!(
+            normalized(Normalize::Cumulative),
+            [0.125, 0.625, 0.875, 1.0]
+        );
+        assert_eq!(
+            normalized(Normalize::Zscore),
+            [-0.8165, 1.633, 0.0, -0.8165]
+        );
+    }
+
+    #[test]
+    fn quantiles() {
+        let price = [3.0, 1.0, 2.0, 2.0, f64::NAN, 4.0];
+        let volume = [2.0, 1.0, 3.0, 2.0, 5.0, 0.0];
+        let probs = [0.0, 0.25, 0.65625, 0.75, 1.0];
+        let at = |interpolation| volume_quantiles(&price, &volume, &probs, interpolation);
+        assert_eq!(at(Interpolation::Lower).unwrap(), [1.0, 2.0, 2.0, 2.0, 3.0]);
+        assert_eq!(
+            at(Interpolation::Higher).unwrap(),
+            [1.0, 2.0, 2.0, 3.0, 3.0]
+        );
+        assert_eq!(
+            at(Interpolation::Midpoint).unwrap(),
+            [1.0, 2.0, 2.0, 2.5, 3.0]
+        );
+        let linear = at(Interpolation::Linear).unwrap();
+        assert_eq!(
+            [linear[0], linear[1], linear[2], linear[4]],
+            [1.0, 1.5, 2.5, 3.0]
+        );
+        assert_eq!(
+            volume_quantiles(&[1.0], &[0.0], &probs, Interpolation::Lower),
+            None
+        );
+    }
+
+    #[test]
+    fn smoothing() {
+        let volume = [0.0, 0.0, 6.0, 0.0, 0.0];
+        let smoothed = |smoothing| {
+            let mut v = volume;
+            smooth(&mut v, smoothing, 10);
+            v
+        };
+        assert_eq!(
+            smoothed(Smoothing::MovingAverage(1)),
+            [0.0, 2.0, 2.0, 2.0, 0.0]
+        );
+        // the share past the edge stays inside the grid
+        let mut edge = [3.0, 0.0, 0.0];
+        smooth(&mut edge, Smoothing::MovingAverage(1), 10);
+        assert_eq!(edge, [1.5, 1.5, 0.0]);
+        let gaussian = smoothed(Smoothing::Gaussian(1.0));
+        assert!((gaussian.iter().sum::<f64>() - 6.0).abs() < 1e-12);
+        assert!(gaussian[2] > gaussian[1] && gaussian[1] == gaussian[3]);
+        // a single traded bin has no spread to take a bandwidth from
+        assert_eq!(smoothed(Smoothing::Silverman), volume);
+        assert!(ProfileConfig::new(3, 4)
+            .with_smoothing(Smoothing::Gaussian(0.0))
+            .validate()
+            .is_err());
+    }
+
+    #[test]
+    fn label_positions() {
+        let grid = Grid::new(10.0, 0.5, 3);
+        let price = [10.1, 10.3, 11.2];
+        let volume = [3.0, -1.0, 2.0];
+        let labels = |label| grid.labels(label, &price, &volume, Some(3));
+        assert_eq!(labels(Label::Lower), [10.0, 10.5, 11.0]);
+        assert_eq!(labels(Label::Center), [10.25, 10.75, 11.25]);
+        assert_eq!(labels(Label::Upper), [10.5, 11.0, 11.5]);
+        assert_eq!(labels(Label::Index), [0.0, 1.0, 2.0]);
+        // the empty middle bin falls back to its center
+        assert_eq!(labels(Label::Vwap), [10.15, 10.75, 11.2]);
+    }
+
+    #[test]
+    fn closed_side() {
+        let price = [0.1, 0.2, 0.3, 0.4, 0.7];
+        let volume = [1.0; 5];
+        let binned = |binning, closed| {
+            let grid = Grid::from_prices(&price, binning, closed).unwrap().unwrap();
+            (
+                grid.labels(Label::Lower, &price, &volume, Some(3)),
+                grid.sum(&price, &volume),
+            )
+        };
+        // inner edges at 0.25, 0.4 and 0.55, the tick at 0.4 sits on one
+        assert_eq!(
+            binned(Binning::Bins(4), Closed::Left),
+            (vec![0.1, 0.25, 0.4, 0.55], vec![2.0, 1.0, 1.0, 1.0])
+        );
+        assert_eq!(
+            binned(Binning::Bins(4), Closed::Right),
+            (vec![0.1, 0.25, 0.4, 0.55], vec![2.0, 2.0, 0.0, 1.0])
+        );
+        // every price is a tick multiple, each sits on an edge
+        assert_eq!(
+            binned(Binning::TickSize(0.1), Closed::Left).1,
+            [1.0, 1.0, 1.0, 1.0, 0.0, 0.0, 1.0]
+        );
+        let (labels, volume) = binned(Binning::TickSize(0.1), Closed::Right);
+        assert_eq!(labels, [0.0, 0.1, 0.2, 0.3, 0.4, 0.5, 0.6]);
+        assert_eq!(volume, [1.0, 1.0, 1.0, 1.0, 0.0, 0.0, 1.0]);
+        let flat = Grid::from_prices(&[2.0, 2.0], Binning::Bins(3), Closed::Right)
+            .unwrap()
+            .unwrap();
+        assert_eq!(flat.sum(&[2.0, 2.0], &[1.0, 1.0]), [2.0, 0.0, 0.0]);
+    }
+
+    #[test]
+    fn tick_size_grid() {
+        let grid = Grid::from_prices(&[10.3, 10.9, 11.0], Binning::TickSize(0.5), Closed::Left)
+            .unwrap()
+            .unwrap();
+        assert_eq!(grid, Grid::new(10.0, 0.5, 3));
+        assert_eq!(
+            grid.sum(&[10.3, 10.9, 11.0], &[1.0, 2.0, 3.0]),
+            [1.0, 2.0, 3.0]
+        );
+        let price = [10.3, 10.9, 11.0];
+        assert_eq!(
+            grid.labels(Label::Lower, &price, &[1.0; 3], None),
+            [10.0, 10.5, 11.0]
+        );
+
+        // a tiny tick over a wide range fails instead of allocating the grid
+        let fine = Binning::TickSize(1e-6);
+        assert!(Grid::from_prices(&[100.0, 200.0], fine, Closed::Left).is_err());
+        let config = ProfileConfig::new(2, 1).with_binning(fine);
+        assert!(rolling_profile(&[100.0, 100.5, 200.0], &[1.0; 3], &config).is_err());
+        let mut acc = ProfileAccumulator::new(config).unwrap();
+        acc.push(100.0, 1.0, 0);
+        acc.push(100.5, 1.0, 1);
+        assert!(acc.try_snapshot().unwrap().is_some());
+        acc.push(200.0, 1.0, 2);
+        assert!(acc.try_snapshot().is_err());
+        assert_eq!(acc.snapshot(), None);
+    }
+
+    #[test]
+    fn profile_round_trip() {
+        let price = [10.0, 10.1, 10.25, 10.3, 10.05];
+        let volume = [3.0, -1.5, 2.0, 7.0, 0.1];
+        let profiles = rolling_profile(&price, &volume, &config()).unwrap();
+        let profile = profiles.last().unwrap().clone().unwrap();
+        let json = profile.to_json().unwrap();
+        assert_eq!(VolumeProfile::from_json(&json).unwrap(), profile);
+        let bytes = profile.to_bytes().unwrap();
+        assert_eq!(VolumeProfile::from_bytes(&bytes).unwrap(), profile);
+
+        // a window without volume has no share per bin
+        let config = ProfileConfig::new(2, 2).with_normalize(Normalize::Sum);
+        let empty = VolumeProfile::from_window(&[1.0, 2.0], &[0.0, 0.0], &config)
+            .unwrap()
+            .unwrap();
+        let json = empty.to_json().unwrap();
+        assert_eq!(
+            json,
+            r#"{"price":[1.25,1.75],"volume":[null,null],"poc_bin":0}"#
+        );
+        for restored in [
+            VolumeProfile::from_json(&json).unwrap(),
+            VolumeProfile::from_bytes(&empty.to_bytes().unwrap()).unwrap(),
+        ] {
+            assert_eq!(restored.price, empty.price);
+            assert!(restored.volume.iter().all(|v| v.is_nan()));
+        }
+    }
+
+    #[test]
+    fn accumulator_matches_rolling() {
+        let price = [
+            10.0,
+            10.4,
+            f64::NAN,
+            10.2,
+            10.9,
+            10.5,
+            10.5,
+            11.3,
+            10.1,
+            10.8,
+        ];
+        let volume = [3.0, 1.0, 2.0, 4.0, 0.5, 2.5, 1.0, 6.0, 2.0, 3.5];
+        let configs = [
+            config(),
+            ProfileConfig::new(4, 3).with_closed(Closed::Right),
+            ProfileConfig::new(5, 1)
+                .with_binning(Binning::TickSize(0.25))
+                .with_label(Label::Vwap)
+                .with_smoothing(Smoothing::MovingAverage(1)),
+            // the running share peaks at the top bin, not at the POC
+            ProfileConfig::new(4, 4)
+                .with_normalize(Normalize::Cumulative)
+                .with_round(1),
+        ];
+        for config in configs {
+            let rolling = rolling_profile(&price, &volume, &config).unwrap();
+            let mut acc = ProfileAccumulator::new(config.clone()).unwrap();
+            for (row, (&p, &v)) in price.iter().zip(&volume).enumerate() {
+                acc.push(p, v, row as i64);
+                assert_eq!(acc.snapshot(), rolling[row], "row {row} of {config:?}");
+                assert_eq!(acc.poc(), rolling[row].as_ref().and_then(|p| p.poc()));
+            }
+        }
+    }
+
+    #[test]
+    fn poc_before_normalization() {
+        let config = ProfileConfig::new(4, 4)
+            .with_label(Label::Lower)
+            .with_normalize(Normalize::Cumulative);
+        let (price, volume) = ([0.0, 1.0, 2.0, 3.0], [1.0, 10.0, 1.0, 1.0]);
+        let profile = VolumeProfile::from_window(&price, &volume, &config)
+            .unwrap()
+            .unwrap();
+        assert_eq!(profile.poc(), Some(0.75));
+        // rounding the volume cannot make ties either
+        let rounded = config.with_normalize(Normalize::Sum).with_round(0);
+        let volume = [1.0, 1.2, 1.1, 1.0];
+        let profile = VolumeProfile::from_window(&price, &volume, &rounded)
+            .unwrap()
+            .unwrap();
+        assert_eq!(profile.volume, [0.0; 4]);
+        // the label 0.75 of the second bin rounds to 1
+        assert_eq!(profile.poc(), Some(1.0));
+        // a profile built by hand takes its largest bin
+        let by_hand = VolumeProfile {
+            price: vec![1.0, 2.0],
+            volume: vec![1.0, 3.0],
+            ..Default::default()
+        };
+        assert_eq!(by_hand.poc(), Some(2.0));
+    }
+
+    #[test]
+    fn accumulator_eviction() {
+        let config = ProfileConfig::new(0, 2).with_label(Label::Lower);
+        let mut acc = ProfileAccumulator::new(config.clone()).unwrap();
+        assert_eq!(acc.snapshot(), None);
+        for (ts, (p, v)) in [(1.0, 5.0), (2.0, 1.0), (3.0, 2.0), (4.0, 4.0), (5.0, 1.0)]
+            .into_iter()
+            .enumerate()
+        {
+            acc.push(p, v, ts as i64);
+        }
+        // the unbounded window keeps every tick until it is evicted
+        assert_eq!(acc.len(), 5);
+        assert_eq!(acc.poc(), Some(3.0));
+        assert_eq!(acc.evict_before(2), 2);
+        assert_eq!(acc.evict_before(2), 0);
+        assert_eq!(
+            acc.snapshot(),
+            VolumeProfile::from_window(&[3.0, 4.0, 5.0], &[2.0, 4.0, 1.0], &config).unwrap()
+        );
+        assert_eq!(acc.poc(), Some(4.0));
+        assert_eq!(acc.value_area(0.7), Some((4.0, 5.0)));
+        assert!(acc.evict());
+        assert_eq!(acc.len(), 2);
+        acc.clear();
+        assert!(acc.is_empty());
+        assert!(!acc.evict());
+        assert_eq!(acc.snapshot(), None);
+
+        // a bounded window evicts the oldest tick on every push once full
+        let mut acc = ProfileAccumulator::new(ProfileConfig::new(2, 2)).unwrap();
+        acc.push(1.0, 1.0, 0);
+        assert!(!acc.is_ready());
+        acc.push(2.0, 1.0, 1);
+        acc.push(3.0, 1.0, 2);
+        assert_eq!(acc.len(), 2);
+        assert_eq!(acc.snapshot().unwrap().price, [2.25, 2.75]);
+    }
+
+    #[test]
+    fn parallel_chunks() {
+        assert_eq!(Parallel::Off.chunk_rows(100_000, 50, 8), None);
+        assert_eq!(Parallel::Auto.chunk_rows(100_000, 50, 1), None);
+        assert_eq!(Parallel::Auto.chunk_rows(1_000, 50, 8), None);
+        assert_eq!(Parallel::Auto.chunk_rows(100_000, 50, 8), Some(3125));
+        assert_eq!(Parallel::Auto.chunk_rows(100_000, 5_000, 8), Some(20_000));
+        assert_eq!(Parallel::Force.chunk_rows(10, 50, 1), Some(3));
+
+        let price: Vec<f64> = (0..50).map(|i| ((i * 7) % 13) as f64 / 4.0).collect();
+        let volume: Vec<f64> = (0..50).map(|i| (i % 5) as f64 + 0.5).collect();
+        let serial = rolling_profile_with(&price, &volume, &config(), Parallel::Off).unwrap();
+        let forced = rolling_profile_with(&price, &volume, &config(), Parallel::Force).unwrap();
+        assert_eq!(forced, serial);
+
+        let rows: Vec<usize> = (0..5_000).collect();
+        assert_eq!(map_rows(5_000, Parallel::Force, |i| i), rows);
+        assert_eq!(map_rows(5_000, Parallel::Off, |i| i), rows);
+    }
+
+    #[test]
+    fn accumulator_round_trip() {
+        let mut acc = ProfileAccumulator::new(config()).unwrap();
+        for (ts, (p, v)) in [(1.0, 2.0), (2.0, 1.0), (3.0, 4.0)].into_iter().enumerate() {
+            acc.push(p, v, ts as i64);
+        }
+        let mut restored = ProfileAccumulator::from_bytes(&acc.to_bytes().unwrap()).unwrap();
+        assert_eq!(restored.snapshot(), acc.snapshot());
+        acc.push(4.0, 1.0, 3);
+        restored.push(4.0, 1.0, 3);
+        assert_eq!(restored.snapshot(), acc.snapshot());
+        let restored = ProfileAccumulator::from_json(&acc.to_json().unwrap()).unwrap();
+        assert_eq!(restored.snapshot(), acc.snapshot());
+        // a missing price is kept as NaN
+        acc.push(f64::NAN, 2.0, 4);
+        let restored = ProfileAccumulator::from_json(&acc.to_json().unwrap()).unwrap();
+        assert_eq!(restored.snapshot(), acc.snapshot());
+        assert_eq!(restored.len(), acc.len());
+
+        // only the live ticks are written, not the evicted ones kept in bulk
+        for ts in 5..2_000 {
+            acc.push(ts as f64, 1.0, ts);
+        }
+        assert!(acc.start > 0);
+        let restored = ProfileAccumulator::from_bytes(&acc.to_bytes().unwrap()).unwrap();
+        assert_eq!((restored.start, restored.price.len()), (0, 3));
+        assert_eq!(restored.snapshot(), acc.snapshot());
+    }
+
+    #[test]
+    fn accumulator_checkpoint_checks() {
+        let config = r#"{"window_size":3,"binning":{"bins":2}}"#;
+        let state = |fields: &str| format!(r#"{{"config":{config},{fields}}}"#);
+        let read = |fields: &str| ProfileAccumulator::from_json(&state(fields));
+        // an older checkpoint with evicted ticks before `start`
+        let old =
+            read(r#""start":1,"price":[1,2,3],"ts":[0,1,2],"weights":[[1,1,1]],"period":null"#)
+                .unwrap();
+        assert_eq!((old.len(), old.price.len()), (2, 2));
+        for corrupt in [
+            r#""start":5,"price":[1],"ts":[0],"weights":[[1]],"period":null"#,
+            r#""start":0,"price":[1],"ts":[0],"weights":[],"period":null"#,
+            r#""start":0,"price":[1,2],"ts":[0],"weights":[[1,1]],"period":null"#,
+            r#""start":0,"price":[1,2],"ts":[0,1],"weights":[[1]],"period":null"#,
+            r#""start":0,"price":[1],"ts":[0],"weights":[[1]],"period":[]"#,
+            r#""start":0,"price":[1,2,3,4],"ts":[0,1,2,3],"weights":[[1,1,1,1]],"period":null"#,
+        ] {
+            assert!(read(corrupt).is_err(), "{corrupt}");
+        }
+        let bad_config = r#"{"config":{"window_size":3,"binning":{"bins":0}},"start":0,"price":[],"ts":[],"weights":[[]],"period":null}"#;
+        assert!(ProfileAccumulator::from_json(bad_config).is_err());
+    }
+}
@@ -1,27 +1,28 @@
 use polars::prelude::*;
 
-// This function is useful for writing functions which
-// accept pairs of List columns. Delete if unneded.
-#[allow(dead_code)]
-pub(crate) fn binary_amortized_elementwise<'a, T, K, F>(
-    ca: &'a ListChunked,
-    weights: &'a ListChunked,
-    mut f: F,
-) -> ChunkedArray<T>
-where
-    T: PolarsDataType,
-    T::Array: ArrayFromIter<Option<K>>,
-    F: FnMut(&Series, &Series) -> Option<K> + Copy,
-{
-    unsafe {
-        ca.amortized_iter()
-            .zip(weights.amortized_iter())
-            .map(|(lhs, rhs)| match (lhs, rhs) {
-                (Some(lhs), Some(rhs)) => f(lhs.as_ref(), rhs.as_ref()),
-                _ => None,
-            })
-            .collect_ca(ca.name())
+// Map every row to the offset of the group it belongs to, so windows can be
+// cut at group boundaries. The key has to be contiguous: every group value
+// must form exactly one run of rows.
+pub(crate) fn group_offsets(by: &Series) -> PolarsResult<Vec<usize>> {
+    let by = by.rechunk();
+    let is_start = by.not_equal_missing(&by.shift(1))?;
+    let mut offsets = Vec::with_capacity(by.len());
+    let mut offset = 0;
+    let mut n_runs = 0;
+    for (idx, start) in is_start.into_iter().enumerate() {
+        if idx == 0 || start.unwrap_or(false) {
+            offset = idx;
+            n_runs += 1;
+        }
+        offsets.push(offset);
     }
+    let n_groups = by.n_unique()?;
+    polars_ensure!(
+        n_runs == n_groups,
+        ComputeError: "group key `{}` is not contiguous: found {} runs for {} groups, sort the frame by the key first",
+        by.name(), n_runs, n_groups
+    );
+    Ok(offsets)
 }
 
 // This function is useful for writing functions which
@@ -1,5 +1,25 @@
 import polars as pl
-from polars_pbv import pbv, pbv_pct, pbv_topn_vp, pbv_topn_v
+import pytest
+from polars_pbv import (
+    pbv,
+    pbv_pct,
+    pbv_topn_vp,
+    pbv_topn_v,
+    pbv_agg,
+    pbv_list,
+    pbv_merge,
+    pbv_distance,
+    pbv_stats,
+    pbv_shape,
+    pbv_position,
+    pbv_naked_poc,
+    pbv_developing,
+    pbv_excess,
+    pbv_weighted,
+    pbv_densify,
+    PbvConfig,
+    pbv_quantile,
+)
 
 
 def test_pbv():
@@ -146,3 +166,802 @@ def test_pbv_top_v():
     print(expected_df)
     print(result_df)
     assert result_df.equals(expected_df)
+
+
+def test_pbv_by():
+    price_col = [100, 101, 102, 103, 104, 105, 106]
+    volume_col = [200, 220, 250, 240, 260, 300, 280]
+    window_size = 3
+    bins = 2
+    df = pl.DataFrame(
+        {
+            "symbol": ["a"] * 4 + ["b"] * 3,
+            "price": price_col,
+            "volume": volume_col,
+        }
+    )
+    expected_df = pl.DataFrame(
+        {
+            "price": [
+                None,
+                None,
+                [100.0, 101.0],
+                [101.0, 102.0],
+                None,
+                None,
+                [104.0, 105.0],
+            ],
+            "volume": [
+                None,
+                None,
+                [200.0, 220.0 + 250.0],
+                [220.0, 250.0 + 240.0],
+                None,
+                None,
+                [260.0, 300.0 + 280.0],
+            ],
+        }
+    ).select(pl.struct("price", "volume").alias("pbv"))
+
+    result = df.select(
+        pbv(
+            "price",
+            "volume",
+            window_size=window_size,
+            bins=bins,
+            center=False,
+            round=2,
+            by="symbol",
+        ).alias("pbv")
+    )
+    assert result.equals(expected_df)
+
+    unsorted = df.with_columns(pl.Series("symbol", ["a", "b", "a", "b", "a", "b", "a"]))
+    with pytest.raises(pl.exceptions.ComputeError):
+        unsorted.select(
+            pbv("price", "volume", window_size=window_size, bins=bins, by="symbol")
+        )
+
+
+def test_pbv_agg():
+    price_col = [100, 101, 102, 103, 104, 105, 106]
+    volume_col = [200, 220, 250, 240, 260, 300, 280]
+    bins = 2
+    df = pl.DataFrame(
+        {
+            "day": [1] * 4 + [2] * 3,
+            "price": price_col,
+            "volume": volume_col,
+        }
+    )
+    expected_df = pl.DataFrame(
+        {
+            "day": [1, 2],
+            "price": [[100.0, 101.5], [104.0, 105.0]],
+            "volume": [[200.0 + 220.0, 250.0 + 240.0], [260.0, 300.0 + 280.0]],
+        }
+    ).select("day", pl.struct("price", "volume").alias("pbv"))
+
+    result = (
+        df.group_by("day", maintain_order=True)
+        .agg(pbv_agg("price", "volume", bins=bins, center=False, round=2).alias("pbv"))
+    )
+    assert result.equals(expected_df)
+
+    whole = df.select(pbv_agg("price", "volume", bins=bins, center=False).alias("pbv"))
+    assert whole.height == 1
+    assert whole["pbv"].struct.field("volume").to_list() == [
+        [200.0 + 220.0 + 250.0, 240.0 + 260.0 + 300.0 + 280.0]
+    ]
+
+
+def test_pbv_list():
+    df = pl.DataFrame(
+        {
+            "price": [[100, 101, 102, 103], [104, 105, 106], []],
+            "volume": [[200, 220, 250, 240], [260, 300, 280], []],
+        }
+    )
+    expected_df = pl.DataFrame(
+        {
+            "price": [[100.0, 101.5], [104.0, 105.0], None],
+            "volume": [[200.0 + 220.0, 250.0 + 240.0], [260.0, 300.0 + 280.0], None],
+        }
+    ).select(pl.struct("price", "volume").alias("pbv"))
+
+    result = df.select(
+        pbv_list("price", "volume", bins=2, center=False, round=2).alias("pbv")
+    )
+    assert result.equals(expected_df)
+
+    mismatched = pl.DataFrame({"price": [[100, 101]], "volume": [[200]]})
+    with pytest.raises(pl.exceptions.ShapeError):
+        mismatched.select(pbv_list("price", "volume", bins=2))
+
+
+def test_pbv_merge():
+    price_col = [100, 101, 102, 103, 104, 105, 106]
+    volume_col = [200, 220, 250, 240, 260, 300, 280]
+    df = pl.DataFrame(
+        {
+            "day": [1] * 4 + [2] * 3,
+            "price": price_col,
+            "volume": volume_col,
+        }
+    )
+    daily = df.group_by("day", maintain_order=True).agg(
+        pbv_agg("price", "volume", bins=2, center=False).alias("pbv")
+    )
+
+    result = daily.select(
+        pbv_merge("pbv", tick_size=1.0, center=False, round=2, input_label="lower")
+    )
+    assert result["pbv"].struct.field("price").to_list() == [
+        [100.0, 101.0, 102.0, 103.0, 104.0, 105.0]
+    ]
+    assert result["pbv"].struct.field("volume").to_list() == [
+        [280.0, 303.33, 326.67, 0.0, 260.0, 580.0]
+    ]
+
+    result = daily.select(pbv_merge("pbv", bins=2, center=False, input_label="lower"))
+    assert result["pbv"].struct.field("volume").to_list() == [[910.0, 840.0]]
+
+    # the input label is its own setting, only the output labels follow `center`
+    centered = daily.select(
+        pbv_merge("pbv", tick_size=1.0, round=2, input_label="lower")
+    )
+    assert centered["pbv"].struct.field("price").to_list() == [
+        [100.5, 101.5, 102.5, 103.5, 104.5, 105.5]
+    ]
+    assert centered["pbv"].struct.field("volume").to_list() == [
+        [280.0, 303.33, 326.67, 0.0, 260.0, 580.0]
+    ]
+    # a single bin is a point unless its width is known
+    single = pl.DataFrame({"pbv": [{"price": [100.0], "volume": [5.0]}]})
+    spread = single.select(
+        pbv_merge("pbv", tick_size=0.5, center=False, input_label="lower", input_width=1.0)
+    )
+    assert spread["pbv"].struct.field("volume").to_list() == [[2.5, 2.5]]
+
+
+def test_pbv_distance():
+    price_col = [100, 101, 102, 103, 104, 105, 106]
+    volume_col = [200, 220, 250, 240, 260, 300, 280]
+    df = pl.DataFrame({"price": price_col, "volume": volume_col})
+
+    result = df.select(
+        pbv_distance("price", "volume", window_size=3, bins=4, k=1).alias("d")
+    ).unnest("d")
+    assert result.columns == ["wasserstein", "kl", "js", "poc_shift"]
+    assert result["wasserstein"].null_count() == 3
+    assert (result["wasserstein"].drop_nulls() > 0).all()
+    assert (result["js"].drop_nulls() <= 0.7).all()
+
+    same = pl.DataFrame({"price": [100, 101, 100, 101, 100], "volume": [1] * 5})
+    result = same.select(
+        pbv_distance("price", "volume", window_size=2, bins=2, k=2).alias("d")
+    ).unnest("d")
+    assert result["wasserstein"].to_list() == [None, None, None, 0.0, 0.0]
+    assert result["poc_shift"].to_list() == [None, None, None, 0.0, 0.0]
+
+    for kwargs in [{"window_size": 0, "bins": 2}, {"window_size": 2, "bins": 0}, {"k": -1}]:
+        with pytest.raises(pl.exceptions.ComputeError):
+            same.select(pbv_distance("price", "volume", **{"window_size": 2, "bins": 2, **kwargs}))
+
+
+def test_pbv_stats():
+    df = pl.DataFrame(
+        {"price": [100, 101, 102, 103, 100, 100, 110, 110], "volume": [1] * 8}
+    )
+
+    result = df.select(
+        pbv_stats("price", "volume", window_size=4, bins=4).alias("s")
+    ).unnest("s")
+    assert result.columns == ["mean", "std", "skew", "kurtosis", "entropy", "bimodality"]
+    uniform = result.row(3, named=True)
+    assert uniform["mean"] == 101.5
+    assert round(uniform["std"], 4) == 1.118
+    assert uniform["skew"] == 0.0
+    assert round(uniform["kurtosis"], 2) == -1.36
+    assert round(uniform["entropy"], 4) == 1.3863
+    two_point = result.row(7, named=True)
+    assert two_point["mean"] == 105.0
+    assert two_point["std"] == 5.0
+    assert round(two_point["bimodality"], 4) == 1.0
+
+    # a window without a price has no stats
+    gaps = pl.DataFrame({"price": [None, None, 100.0, 101.0], "volume": [1.0] * 4})
+    result = gaps.select(pbv_stats("price", "volume", window_size=2, bins=2).alias("s"))
+    assert result["s"].struct.field("mean").to_list() == [None, None, 100.0, 100.5]
+    with pytest.raises(pl.exceptions.ComputeError):
+        df.select(pbv_stats("price", "volume", window_size=0, bins=4))
+
+
+def test_pbv_shape():
+    price_col = [100, 101, 102, 103, 104] * 4
+    volume_col = (
+        [1, 1, 2, 5, 8] + [8, 5, 2, 1, 1] + [1, 3, 8, 3, 1] + [6, 8, 1, 7, 5]
+    )
+    df = pl.DataFrame({"price": price_col, "volume": volume_col})
+
+    result = df.select(
+        pbv_shape("price", "volume", window_size=5, bins=5).alias("s")
+    ).unnest("s")
+    assert result.columns == ["shape", "poc_position", "mass_center", "valley_ratio"]
+    assert result["shape"].gather([4, 9, 14, 19]).to_list() == ["P", "b", "D", "B"]
+    assert result["poc_position"].gather([4, 9]).to_list() == [0.9, 0.1]
+    assert result["shape"].null_count() == 4
+    assert result["shape"].dtype == pl.Enum(["P", "b", "D", "B"])
+    # a mass center of 0.71 is not far enough up for a stricter threshold
+    strict = df.select(
+        pbv_shape("price", "volume", window_size=5, bins=5, mass=0.75).alias("s")
+    ).unnest("s")
+    assert strict["shape"].gather([4, 9]).to_list() == ["D", "D"]
+    with pytest.raises(pl.exceptions.ComputeError):
+        df.select(pbv_shape("price", "volume", window_size=0, bins=5))
+
+
+def test_pbv_position():
+    df = pl.DataFrame(
+        {"price": [100, 101, 102, 103, 104, 102], "volume": [1, 3, 8, 3, 1, 8]}
+    )
+
+    result = df.select(
+        pbv_position("price", "volume", window_size=5, bins=5, tick_size=0.5).alias(
+            "p"
+        )
+    ).unnest("p")
+    assert result.columns == [
+        "percentile",
+        "poc_distance_ticks",
+        "poc_distance_bins",
+        "in_value_area",
+    ]
+    assert result.row(4) == (1.0, 4.0, 2.5, False)
+    assert result["in_value_area"].to_list() == [None] * 4 + [False, True]
+
+    # without a last price there is nothing to locate
+    gaps = pl.DataFrame({"price": [100.0, 101.0, None], "volume": [1.0] * 3})
+    result = gaps.select(pbv_position("price", "volume", window_size=2, bins=2).alias("p"))
+    assert result["p"].struct.field("percentile").to_list() == [None, 1.0, None]
+    for kwargs in [{"window_size": 0}, {"value_area": 1.5}]:
+        with pytest.raises(pl.exceptions.ComputeError):
+            df.select(pbv_position("price", "volume", **{"window_size": 5, "bins": 5, **kwargs}))
+
+
+def test_pbv_metric_config():
+    df = pl.DataFrame(
+        {"price": [100, 101, 102, 103, 104, 102], "volume": [1, 3, 8, 3, 1, 8]}
+    )
+    config = PbvConfig(window_size=5, bins=5)
+    res = df.select(
+        legacy=pbv_stats("price", "volume", window_size=5, bins=5),
+        config=pbv_stats("price", "volume", config=config),
+        distance=pbv_distance("price", "volume", 4, config=config, k=1),
+        distance_legacy=pbv_distance("price", "volume", 4, 5, k=1),
+    )
+    assert res["legacy"].to_list() == res["config"].to_list()
+    assert res["distance"].to_list() == res["distance_legacy"].to_list()
+
+    # the tick size of the config grid measures the POC distance
+    ticks = PbvConfig(window_size=5, tick_size=0.5)
+    result = df.select(pbv_position("price", "volume", config=ticks).alias("p")).unnest("p")
+    assert result["poc_distance_ticks"].to_list()[4] == 3.5
+
+    daily = df.select(pbv_agg("price", "volume", bins=2).alias("pbv"))
+    assert (
+        daily.select(pbv_merge("pbv", config=PbvConfig(bins=2))).to_dicts()
+        == daily.select(pbv_merge("pbv", bins=2)).to_dicts()
+    )
+
+
+def test_pbv_naked_poc():
+    df = pl.DataFrame(
+        {
+            "session": ["a"] * 3 + ["b"] * 3 + ["c"] * 3,
+            "price": [100, 101, 101, 102, 103, 104, 105, 103, 101],
+            "volume": [1, 5, 5, 1, 8, 1, 1, 1, 1],
+        }
+    )
+
+    result = df.select(
+        pbv_naked_poc("price", "volume", "session", bins=2).alias("n")
+    ).unnest("n")
+    assert result["poc"].to_list() == [[]] * 3 + [[100.75]] * 3 + [
+        [100.75, 103.5],
+        [100.75],
+        [100.75],
+    ]
+    assert result["session"].to_list() == [[]] * 3 + [["a"]] * 3 + [
+        ["a", "b"],
+        ["a"],
+        ["a"],
+    ]
+    assert result["age"].to_list() == [[]] * 3 + [[1]] * 3 + [[2, 1], [2], [2]]
+
+
+def test_pbv_developing():
+    df = pl.DataFrame(
+        {
+            "session": [1] * 4 + [2] * 2,
+            "price": [100.0, 100.5, 99.5, 100.5, 101.0, 100.0],
+            "volume": [1, 5, 5, 1, 8, 1],
+        }
+    )
+
+    result = df.select(
+        pbv_developing("price", "volume", tick_size=0.5, by="session").alias("d")
+    ).unnest("d")
+    assert result.columns == ["poc", "vah", "val"]
+    assert result["poc"].to_list() == [100.0, 100.5, 99.5, 100.5, 101.0, 101.0]
+    assert result["vah"].to_list() == [100.0, 100.5, 100.5, 100.5, 101.0, 101.0]
+    assert result["val"].to_list() == [100.0, 100.5, 99.5, 99.5, 101.0, 101.0]
+
+    gaps = pl.DataFrame(
+        {
+            "price": [100.0, float("nan"), 100.5, float("inf"), 100.5],
+            "volume": [1.0, 9.0, float("nan"), 9.0, 3.0],
+        }
+    )
+    result = gaps.select(pbv_developing("price", "volume", tick_size=0.5).alias("d"))
+    assert result["d"].struct.field("poc").to_list() == [100.0] * 4 + [100.5]
+
+    wide = pl.DataFrame({"price": [1.0, 1e12], "volume": [1.0, 1.0]})
+    with pytest.raises(pl.exceptions.ComputeError):
+        wide.select(pbv_developing("price", "volume", tick_size=1.0))
+    with pytest.raises(pl.exceptions.ComputeError):
+        df.select(pbv_developing("price", "volume", tick_size=0.5, value_area=1.5))
+
+    # prices fall into tick bins like the tick size grids of `pbv`, 100.4 is in
+    # the bin from 100.0 and not rounded up to 100.5
+    off_tick = pl.DataFrame({"price": [100.2, 100.4, 100.4], "volume": [1.0, 1.0, 1.0]})
+    result = off_tick.select(pbv_developing("price", "volume", tick_size=0.5).alias("d"))
+    assert result["d"].struct.field("poc").to_list() == [100.0] * 3
+    closed = PbvConfig(tick_size=0.5, closed="right", label="upper")
+    result = df.select(
+        right=pbv_developing("price", "volume", by="session", config=closed),
+        left=pbv_developing("price", "volume", tick_size=0.5, by="session"),
+    )
+    assert result["right"].to_list() == result["left"].to_list()
+
+
+def test_pbv_excess():
+    df = pl.DataFrame(
+        {"price": [100, 100, 101, 102, 103, 103], "volume": [5, 5, 1, 1, 3, 3]}
+    )
+
+    result = df.select(
+        pbv_excess("price", "volume", window_size=6, bins=3).alias("e")
+    ).unnest("e")
+    last = result.row(5, named=True)
+    assert last["single_print_lower"] == [101.0]
+    assert last["single_print_upper"] == [102.0]
+    assert (last["poor_high_lower"], last["poor_high_upper"]) == (102.0, 103.0)
+    assert (last["poor_low_lower"], last["poor_low_upper"]) == (100.0, 101.0)
+
+    result = df.select(
+        pbv_excess("price", "volume", window_size=6, bins=3, poor_ratio=1.5).alias("e")
+    ).unnest("e")
+    assert result["poor_high_lower"][5] is None
+    assert result["poor_low_lower"][5] == 100.0
+    for kwargs in [{"window_size": 0}, {"bins": 0}, {"poor_ratio": 0.0}]:
+        with pytest.raises(pl.exceptions.ComputeError):
+            df.select(pbv_excess("price", "volume", **{"window_size": 6, "bins": 3, **kwargs}))
+
+
+def test_pbv_tpo():
+    df = pl.DataFrame(
+        {
+            "price": [100, 100, 101, 101, 101],
+            "volume": [1, 2, 3, 4, 5],
+            "bracket": [1, 1, 1, 2, 2],
+        }
+    )
+
+    result = df.select(
+        pbv("price", "volume", window_size=5, bins=2, center=False, weight="tpo").alias(
+            "pbv"
+        )
+    ).unnest("pbv")
+    assert result.columns == ["price", "tpo"]
+    assert result["tpo"][4].to_list() == [2.0, 3.0]
+
+    result = df.select(
+        pbv(
+            "price",
+            "volume",
+            window_size=5,
+            bins=2,
+            center=False,
+            weight="both",
+            period="bracket",
+        ).alias("pbv")
+    ).unnest("pbv")
+    assert result.columns == ["price", "volume", "tpo"]
+    assert result["volume"][4].to_list() == [3.0, 12.0]
+    assert result["tpo"][4].to_list() == [1.0, 2.0]
+
+    result = df.select(
+        pbv_agg("price", "volume", bins=2, weight="tpo", period="bracket").alias("pbv")
+    )
+    assert result["pbv"].struct.field("tpo").to_list() == [[1.0, 2.0]]
+
+
+def test_pbv_weighted():
+    df = pl.DataFrame(
+        {
+            "price": [1.0, 2.0, 3.0, 4.0],
+            "delta": [1.0, -3.0, 2.0, -4.0],
+        }
+    )
+    res = df.select(
+        raw=pbv_weighted("price", "delta", 4, 2, center=False),
+        pct=pbv_weighted("price", "delta", 4, 2, center=False, pct=True),
+        signed=pbv_weighted(
+            "price", "delta", 4, 2, center=False, pct=True, signed=True
+        ),
+    )
+    assert res["raw"].struct.fields == ["price", "weight"]
+    last = res.row(3, named=True)
+    assert last["raw"]["price"] == [1.0, 2.5]
+    assert last["raw"]["weight"] == [-2.0, -2.0]
+    assert last["pct"]["weight"] == [0.5, 0.5]
+    assert last["signed"]["weight"] == [-0.5, -0.5]
+
+
+def test_pbv_multi_weight():
+    df = pl.DataFrame(
+        {
+            "price": [1.0, 2.0, 3.0, 4.0],
+            "volume": [1.0, 3.0, 2.0, 4.0],
+            "notional": [10.0, 30.0, 20.0, 40.0],
+        }
+    )
+    res = df.select(
+        pbv=pbv("price", ["volume", "notional"], 3, 2, center=False),
+        single=pbv("price", "volume", 3, 2, center=False),
+    )
+    assert res["pbv"].struct.fields == ["price", "volume", "notional"]
+    out = res["pbv"].struct.unnest()
+    assert out["price"].to_list() == [None, None, [1.0, 2.0], [2.0, 3.0]]
+    assert out["volume"].to_list() == [None, None, [1.0, 5.0], [3.0, 6.0]]
+    assert out["notional"].to_list() == [None, None, [10.0, 50.0], [30.0, 60.0]]
+    assert out["volume"].to_list() == res["single"].struct.field("volume").to_list()
+
+
+def test_pbv_sparse():
+    df = pl.DataFrame(
+        {
+            "price": [1.0, 1.0, 3.0, 4.0, 4.0],
+            "volume": [1.0, 3.0, 2.0, 4.0, 1.0],
+        }
+    )
+    res = df.select(
+        sparse=pbv("price", "volume", 3, 6, center=False, sparse=True),
+        dense=pbv("price", "volume", 3, 6, center=False),
+    )
+    assert res["sparse"].to_list()[2] == {
+        "bin_count": 6,
+        "label_start": 1.0,
+        "label_step": 0.3333333333333333,
+        "bins": [
+            {"bin_index": 0, "price": 1.0, "volume": 4.0},
+            {"bin_index": 5, "price": 2.6666666666666665, "volume": 2.0},
+        ],
+    }
+    res = res.with_columns(
+        densified=pbv_densify("sparse"),
+        forced=pbv_densify("sparse", 6),
+    )
+    assert res["forced"].to_list() == res["densified"].to_list()
+    assert res["densified"].struct.field("volume").to_list() == (
+        res["dense"].struct.field("volume").to_list()
+    )
+    for got, want in zip(
+        res["densified"].struct.field("price").to_list()[2:],
+        res["dense"].struct.field("price").to_list()[2:],
+    ):
+        assert got == pytest.approx(want)
+
+
+def test_pbv_densify_tick_size():
+    df = pl.DataFrame(
+        {
+            "price": [1.0, 2.0, 3.0, 4.0, 9.0, 9.0],
+            "volume": [1.0, 2.0, 3.0, 4.0, 5.0, 6.0],
+        }
+    )
+    config = PbvConfig(window_size=3, tick_size=1.0, label="lower")
+    res = df.select(
+        sparse=pbv("price", "volume", config=config, sparse=True),
+        dense=pbv("price", "volume", config=config),
+    ).with_columns(densified=pbv_densify("sparse"))
+    counts = [len(v) for v in res["dense"].struct.field("volume").to_list()[2:]]
+    assert len(set(counts)) > 1
+    assert res["densified"].struct.field("volume").to_list() == (
+        res["dense"].struct.field("volume").to_list()
+    )
+    assert res["densified"].struct.field("price").to_list() == (
+        res["dense"].struct.field("price").to_list()
+    )
+
+
+
+def test_pbv_densify_labels():
+    df = pl.DataFrame(
+        {
+            "price": [2.0, 2.0, 2.0, 1.0, 4.5, 4.0],
+            "volume": [1.0, 3.0, 2.0, 4.0, 1.0, 2.0],
+        }
+    )
+    for label in ["vwap", "upper", "index"]:
+        config = PbvConfig(window_size=3, bins=4, label=label)
+        res = df.select(
+            sparse=pbv("price", "volume", config=config, weight="both", sparse=True),
+            dense=pbv("price", "volume", config=config, weight="both"),
+        ).with_columns(densified=pbv_densify("sparse"))
+        for field in ["volume", "tpo"]:
+            assert res["densified"].struct.field(field).to_list() == (
+                res["dense"].struct.field(field).to_list()
+            )
+        for got, want in zip(
+            res["densified"].struct.field("price").to_list()[2:],
+            res["dense"].struct.field("price").to_list()[2:],
+        ):
+            assert got == pytest.approx(want)
+
+def test_pbv_config():
+    df = pl.DataFrame(
+        {
+            "price": [100, 101, 102, 103, 104, 105, 106],
+            "volume": [200, 220, 250, 240, 260, 300, 280],
+        }
+    )
+    config = PbvConfig(window_size=6, bins=3, label="lower", round=2)
+    res = df.select(
+        legacy=pbv("price", "volume", 6, 3, center=False, round=2),
+        config=pbv("price", "volume", config=config),
+        tick=pbv("price", "volume", config=config.with_options(bins=None, tick_size=2.0)),
+    )
+    assert res["legacy"].to_list() == res["config"].to_list()
+    last = res["tick"].to_list()[-1]
+    assert last["price"] == [100.0, 102.0, 104.0, 106.0]
+    assert last["volume"] == [220.0, 490.0, 560.0, 280.0]
+    with pytest.raises(ValueError):
+        PbvConfig(window_size=6)
+    with pytest.raises(Exception):
+        df.select(pbv("price", "volume", config=PbvConfig(window_size=6, bins=0)))
+    # a tiny tick over the window range would need millions of bins
+    with pytest.raises(pl.exceptions.ComputeError):
+        df.select(pbv("price", "volume", config=PbvConfig(window_size=6, tick_size=1e-6)))
+
+
+
+def test_pbv_config_with_fields():
+    df = pl.DataFrame(
+        {
+            "price": [100, 101, 102, 103, 104, 105, 106],
+            "volume": [200, 220, 250, 240, 260, 300, 280],
+        }
+    )
+    # the fields passed next to a config override it
+    res = df.select(
+        mixed=pbv("price", "volume", 6, config=PbvConfig(bins=3)),
+        rounded=pbv("price", "volume", config=PbvConfig(window_size=6, bins=3, round=2), round=0, center=False),
+        legacy=pbv("price", "volume", 6, 3),
+        legacy_rounded=pbv("price", "volume", 6, 3, center=False, round=0),
+    )
+    assert res["mixed"].to_list() == res["legacy"].to_list()
+    assert res["rounded"].to_list() == res["legacy_rounded"].to_list()
+    assert res["mixed"].to_list()[-1] is not None
+
+
+
+def test_pbv_config_pct():
+    df = pl.DataFrame(
+        {
+            "price": [100, 101, 102, 103, 104, 105, 106],
+            "volume": [200, 220, 250, 240, 260, 300, 280],
+        }
+    )
+    config = PbvConfig(window_size=6, bins=3, label="lower", round=2)
+    # `pct` scales a config left at normalize="none" in every function
+    res = df.select(
+        pct=pbv_pct("price", "volume", config=config),
+        weighted=pbv_weighted("price", "volume", config=config, pct=True),
+        topn=pbv_topn_v("price", "volume", config=config, pct=True),
+        max=pbv_weighted(
+            "price", "volume", config=config.with_options(normalize="max"), pct=True
+        ),
+    )
+    last = res.row(-1, named=True)
+    assert last["weighted"]["volume"] == last["pct"]["volume"]
+    assert last["topn"] == [max(last["pct"]["volume"])]
+    assert max(last["max"]["volume"]) == 1.0
+    agg = df.select(pbv_agg("price", "volume", config=config, pct=True).alias("pbv"))
+    assert sum(agg["pbv"].to_list()[0]["volume"]) == pytest.approx(1.0, abs=0.01)
+    listed = df.select(
+        pbv_list(
+            pl.col("price").implode(), pl.col("volume").implode(), config=config, pct=True
+        ).alias("pbv")
+    )
+    assert sum(listed["pbv"].to_list()[0]["volume"]) == pytest.approx(1.0, abs=0.01)
+
+
+def test_pbv_config_partial():
+    df = pl.DataFrame(
+        {
+            "price": [100.0, 100.5, 99.5, 100.5, 101.0, 100.0],
+            "volume": [1, 5, 5, 1, 8, 1],
+        }
+    )
+    # only the window of the config applies to the quantiles and only the
+    # tick size to the developing profile
+    config = PbvConfig(window_size=3, tick_size=0.5, label="lower")
+    res = df.select(
+        quantile=pbv_quantile("price", "volume", config=config),
+        plain_quantile=pbv_quantile("price", "volume", 3, [0.5]),
+        developing=pbv_developing("price", "volume", config=config),
+        plain_developing=pbv_developing("price", "volume", tick_size=0.5),
+    )
+    assert res["quantile"].to_list() == res["plain_quantile"].to_list()
+    assert res["developing"].to_list() == res["plain_developing"].to_list()
+    with pytest.raises(pl.exceptions.ComputeError):
+        df.select(pbv_developing("price", "volume", config=config.with_options(tick_size=None, bins=3)))
+    with pytest.raises(ValueError):
+        pbv_quantile("price", "volume")
+
+def test_pbv_normalize():
+    df = pl.DataFrame(
+        {
+            "price": [100, 101, 102, 103, 104, 105, 106],
+            "volume": [200, 220, 250, 240, 260, 300, 280],
+        }
+    )
+    config = PbvConfig(window_size=6, tick_size=2.0, label="lower", round=2)
+    res = df.select(
+        max=pbv("price", "volume", config=config, normalize="max"),
+        density=pbv("price", "volume", config=config, normalize="density"),
+        cumulative=pbv("price", "volume", config=config, normalize="cumulative"),
+        zscore=pbv("price", "volume", config=config, normalize="zscore"),
+        none=pbv_pct("price", "volume", config=config, normalize="none"),
+    )
+    last = {name: res[name].to_list()[-1]["volume"] for name in res.columns}
+    assert last["max"] == [0.39, 0.88, 1.0, 0.5]
+    assert last["density"] == [110.0, 245.0, 280.0, 140.0]
+    assert last["cumulative"] == [0.14, 0.46, 0.82, 1.0]
+    assert last["zscore"] == [-1.19, 0.73, 1.22, -0.76]
+    assert last["none"] == [220.0, 490.0, 560.0, 280.0]
+
+
+def test_pbv_quantile():
+    df = pl.DataFrame(
+        {
+            "price": [3.0, 1.0, 2.0, 2.0, None, 4.0],
+            "volume": [2, 1, 3, 2, 5, 0],
+            "day": [1, 1, 1, 1, 1, 2],
+        }
+    )
+    quantiles = [0.0, 0.25, 0.65625, 0.75, 1.0]
+    res = df.select(
+        lower=pbv_quantile("price", "volume", 6, quantiles, interpolation="lower"),
+        higher=pbv_quantile("price", "volume", 6, quantiles, interpolation="higher"),
+        midpoint=pbv_quantile("price", "volume", 6, quantiles, interpolation="midpoint"),
+        linear=pbv_quantile("price", "volume", 6, [0.25, 0.65625]),
+        by=pbv_quantile("price", "volume", 2, [0.5], interpolation="lower", by="day"),
+    )
+    last = res.row(-1, named=True)
+    assert last["lower"] == [1.0, 2.0, 2.0, 2.0, 3.0]
+    assert last["higher"] == [1.0, 2.0, 2.0, 3.0, 3.0]
+    assert last["midpoint"] == [1.0, 2.0, 2.0, 2.5, 3.0]
+    assert last["linear"] == [1.5, 2.5]
+    assert res["by"].to_list() == [None, [3.0], [2.0], [2.0], [2.0], None]
+
+
+def test_pbv_smoothing():
+    df = pl.DataFrame(
+        {
+            "price": [100, 101, 102, 103, 104, 105, 106],
+            "volume": [200, 220, 250, 240, 260, 300, 280],
+        }
+    )
+    config = PbvConfig(window_size=6, tick_size=2.0, label="lower", round=2)
+    res = df.select(
+        moving=pbv("price", "volume", config=config, smoothing=("moving_average", 1)),
+        gaussian=pbv("price", "volume", config=config, smoothing=("gaussian", 1.0)),
+        silverman=pbv(
+            "price", "volume", config=config.with_options(smoothing="silverman")
+        ),
+        max=pbv(
+            "price", "volume", config=config, smoothing=("gaussian", 1.0), normalize="max"
+        ),
+    )
+    last = {name: res[name].to_list()[-1]["volume"] for name in res.columns}
+    assert last["moving"] == [273.33, 460.0, 490.0, 326.67]
+    for name in ["gaussian", "silverman"]:
+        assert sum(last[name]) == pytest.approx(1550.0, abs=0.05)
+    assert max(last["max"]) == 1.0
+    assert last["max"].index(1.0) == 2
+
+
+def test_pbv_label():
+    df = pl.DataFrame(
+        {
+            "price": [10.1, 10.3, 11.0, 11.2, 11.5],
+            "volume": [3, 1, 2, 2, 4],
+        }
+    )
+    labels = {
+        label: pbv("price", "volume", 5, 3, round=3, label=label)
+        for label in ["lower", "center", "upper", "vwap", "index"]
+    }
+    res = df.select(**labels)
+    last = {name: res[name].to_list()[-1]["price"] for name in res.columns}
+    assert last["lower"] == [10.1, 10.567, 11.033]
+    assert last["center"] == [10.333, 10.8, 11.267]
+    assert last["upper"] == [10.567, 11.033, 11.5]
+    assert last["vwap"] == [10.15, 11.0, 11.4]
+    assert last["index"] == [0.0, 1.0, 2.0]
+    assert res["lower"].to_list() == df.select(
+        pbv("price", "volume", 5, 3, center=False, round=3)
+    ).to_series().to_list()
+
+
+def test_pbv_closed():
+    df = pl.DataFrame(
+        {
+            "price": [0.1, 0.2, 0.3, 0.4, 0.7],
+            "volume": [1, 1, 1, 1, 1],
+        }
+    )
+    res = df.select(
+        left=pbv("price", "volume", 5, 4, center=False, round=3),
+        right=pbv("price", "volume", 5, 4, center=False, round=3, closed="right"),
+        tick=pbv(
+            "price",
+            "volume",
+            config=PbvConfig(window_size=5, tick_size=0.1, label="lower", round=3),
+            closed="right",
+        ),
+    )
+    last = res.row(-1, named=True)
+    assert last["left"]["price"] == [0.1, 0.25, 0.4, 0.55]
+    assert last["left"]["volume"] == [2.0, 1.0, 1.0, 1.0]
+    assert last["right"]["volume"] == [2.0, 2.0, 0.0, 1.0]
+    assert last["tick"]["price"] == [0.0, 0.1, 0.2, 0.3, 0.4, 0.5, 0.6]
+    assert last["tick"]["volume"] == [1.0, 1.0, 1.0, 1.0, 0.0, 0.0, 1.0]
+
+
+def test_pbv_parallel():
+    n = 3000
+    df = pl.DataFrame(
+        {
+            "symbol": [i * 3 // n for i in range(n)],
+            "price": [((i * 37) % 101) / 10 for i in range(n)],
+            "volume": [float(i % 7 + 1) for i in range(n)],
+        }
+    )
+    res = df.select(
+        off=pbv("price", "volume", 50, 8, by="symbol", parallel="off"),
+        auto=pbv("price", "volume", 50, 8, by="symbol"),
+        force=pbv("price", "volume", 50, 8, by="symbol", parallel="force"),
+        topn=pbv_topn_v("price", "volume", 50, 8, 3, parallel="force"),
+        topn_off=pbv_topn_v("price", "volume", 50, 8, 3, parallel="off"),
+    )
+    assert res["auto"].to_list() == res["off"].to_list()
+    assert res["force"].to_list() == res["off"].to_list()
+    assert res["topn"].to_list() == res["topn_off"].to_list()
+    # windows restart at every symbol
+    assert res["off"].struct.field("volume")[1048] is None
+    assert res["off"].struct.field("volume")[1049] is not None
+    # the metrics and quantiles split their rows the same way
+    metrics = df.select(
+        stats=pbv_stats("price", "volume", 50, 8, parallel="force"),
+        stats_off=pbv_stats("price", "volume", 50, 8, parallel="off"),
+        distance=pbv_distance("price", "volume", 50, 8, k=5, parallel="force"),
+        distance_off=pbv_distance("price", "volume", 50, 8, k=5, parallel="off"),
+        quantile=pbv_quantile("price", "volume", 50, [0.5], by="symbol", parallel="force"),
+        quantile_off=pbv_quantile("price", "volume", 50, [0.5], by="symbol", parallel="off"),
+    )
+    for name in ["stats", "distance", "quantile"]:
+        assert metrics[name].to_list() == metrics[f"{name}_off"].to_list()